@@ -0,0 +1,335 @@
+use std::{collections::HashMap, io::Write, path::Path, sync::Arc, time};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use sqlx::{Pool, Sqlite};
+use tokio::fs;
+
+use crate::{
+    config::{Config, ListenAddr, SharedConfig},
+    db::{self, backup, DbError},
+    library_system::{
+        model::{LibraryInfo, Source},
+        LibrarySystem, Replace,
+    },
+    meta::{Commands, ConfigCommands, DbCommands, LibraryCommands, PluginCommands, UserCommands},
+    plugin_system::{
+        model::{validate_plugin, PluginsContext},
+        PluginSystem,
+    },
+    share_system::ShareSystem,
+    user_system::{model::UserToCreate, UserSystem},
+};
+
+/// Tables written by `db export`, in the order they are imported.
+const EXPORT_TABLES: [&str; 6] = ["roles", "users", "hidden_libraries", "api_keys", "shares", "plugins"];
+const EXPORT_VERSION: u64 = 1;
+const HEALTHCHECK_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Run the offline commands, everything except `serve`.
+pub async fn run(command: Commands, config: Config) -> Result<()> {
+    let config = Arc::new(config);
+    match command {
+        Commands::Serve => unreachable!("`serve` is run by the server."),
+        Commands::Config {
+            command: ConfigCommands::Check,
+        } => {
+            println!("{}", config.to_redacted_toml()?);
+            Ok(())
+        }
+        Commands::Scan => scan(config).await,
+        Commands::User { command } => user(command, config).await,
+        Commands::Library {
+            command: LibraryCommands::List,
+        } => list_libraries(&config).await,
+        Commands::Plugin { command } => plugin(command, config).await,
+        Commands::Db { command } => database(command, config).await,
+        Commands::Healthcheck => healthcheck(&config).await,
+    }
+}
+
+async fn open_db(config: &Arc<Config>) -> Result<Pool<Sqlite>> {
+    if config.data_path.is_none() {
+        bail!("The `data_path` must be settled, the database lives in it.");
+    }
+    db::init(config.clone()).await
+}
+
+fn read_password(password: Option<String>) -> Result<String> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            eprint!("Password: ");
+            std::io::stderr().flush()?;
+            let mut line = String::new();
+            std::io::stdin().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_owned()
+        }
+    };
+    if password.len() < 8 || !password.is_ascii() {
+        bail!("Password must be more than 8 ascii characters.");
+    }
+    Ok(password)
+}
+
+async fn scan(config: Arc<Config>) -> Result<()> {
+    let db = open_db(&config).await?;
+    let shared = SharedConfig::new(config.as_ref().clone());
+    let plugin_system = PluginSystem::new(db.clone(), shared.clone()).await?;
+    plugin_system.reload().await;
+    let library_system = LibrarySystem::new(db, shared).await?;
+
+    let start = time::Instant::now();
+    let (library_paths, total_path) = library_system.scan(&config.libraries).await;
+    let files: HashMap<String, usize> = library_paths.iter().map(|(library, paths)| (library.title.clone(), paths.len())).collect();
+    library_system.perform_medias(&plugin_system, library_paths, total_path, Replace::All).await?;
+
+    println!("{:<32} {:>8} {:>8} {:>8}", "LIBRARY", "FILES", "MEDIAS", "SKIPPED");
+    let mut total_media = 0;
+    for library in &config.libraries {
+        let files = files.get(&library.title).copied().unwrap_or(0);
+        let medias = library_system.get_total_media(Source::Library(&library.title), None, &[]).await?;
+        total_media += medias;
+        println!("{:<32} {:>8} {:>8} {:>8}", library.title, files, medias, files.saturating_sub(medias));
+    }
+    println!("Scanned {total_path} files into {total_media} medias in {:.2}s.", start.elapsed().as_secs_f32());
+    Ok(())
+}
+
+async fn user(command: UserCommands, config: Arc<Config>) -> Result<()> {
+    let user_system = UserSystem::new(open_db(&config).await?).await?;
+    let get_user = |username: String| {
+        let user_system = user_system.clone();
+        async move {
+            match user_system.get_user(&username).await {
+                Err(DbError::NotFound(_)) => bail!("No found user `{username}`."),
+                result => Ok(result?),
+            }
+        }
+    };
+    match command {
+        UserCommands::List => {
+            let total = user_system.get_total_user(None).await?;
+            println!("{:<24} {:<24} {:<16}", "USERNAME", "ALIAS", "ROLE");
+            for user in user_system.get_users(0, total, None).await? {
+                println!("{:<24} {:<24} {:<16}", user.username, user.alias, user.role);
+            }
+        }
+        UserCommands::Add {
+            username,
+            alias,
+            role,
+            password,
+        } => {
+            if username.len() < 4 || !username.is_ascii() {
+                bail!("Username must be more than 4 ascii characters.");
+            }
+            if username == "guest" {
+                bail!("Username can't same with `guest`, enable the guest by setup.");
+            }
+            if user_system.exists_user(&username).await? {
+                bail!("Already exists user `{username}`.");
+            }
+            if !user_system.exists_role(&role).await? {
+                bail!("No found role `{role}`.");
+            }
+            let password = read_password(password)?;
+            let alias = alias.unwrap_or_else(|| username.clone());
+            user_system.create_user(UserToCreate { alias, username: username.clone(), password }, &role).await?;
+            println!("Added user `{username}` with role `{role}`.");
+        }
+        UserCommands::Remove { username } => {
+            let user = get_user(username).await?;
+            user_system.delete_user(&user).await?;
+            println!("Removed user `{}`.", user.username);
+        }
+        UserCommands::ResetPassword { username, password } => {
+            let user = get_user(username).await?;
+            let password = read_password(password)?;
+            let to_update = UserToCreate {
+                alias: user.alias.clone(),
+                username: user.username.clone(),
+                password,
+            };
+            user_system.update_user(&user, to_update).await?;
+            println!("Reset the password of `{}`.", user.username);
+        }
+    }
+    Ok(())
+}
+
+async fn list_libraries(config: &Config) -> Result<()> {
+    println!("{:<32} {:>8}  PATH", "LIBRARY", "FILES");
+    for library in &config.libraries {
+        let files = library.fetch().await.len();
+        println!("{:<32} {:>8}  {}", library.title, files, library.path.display());
+    }
+    Ok(())
+}
+
+async fn plugin(command: PluginCommands, config: Arc<Config>) -> Result<()> {
+    let plugin_system = PluginSystem::new(open_db(&config).await?, SharedConfig::new(config.as_ref().clone())).await?;
+    plugin_system.reload().await;
+    let plugins = plugin_system.get_plugins().await;
+    match command {
+        PluginCommands::List => {
+            for plugin in &plugins {
+                let status = match (&plugin.manifest, &plugin.error) {
+                    (Some(manifest), None) if plugin.enabled => format!("{} v{}", manifest.name, manifest.version),
+                    (Some(manifest), None) => format!("{} v{} (disabled)", manifest.name, manifest.version),
+                    (_, error) => format!("rejected: {}", error.as_deref().unwrap_or("unknown")),
+                };
+                println!("{:<40} {:<32} {}", plugin.name, status, plugin.path.display());
+            }
+        }
+        PluginCommands::Validate => {
+            let mut failed = 0;
+            for plugin in &plugins {
+                let name = &plugin.name;
+                match validate_plugin(plugin).await {
+                    Ok(()) => println!("{name:<40} ok"),
+                    Err(err) => {
+                        failed += 1;
+                        println!("{name:<40} {err:#}");
+                    }
+                }
+            }
+            if failed > 0 {
+                bail!("{failed} of {} plugins are invalid.", plugins.len());
+            }
+        }
+        PluginCommands::Run { name, file } => {
+            let Some(mut plugin) = plugins.into_iter().find(|plugin| plugin.name == name) else {
+                bail!("No found plugin `{name}`.");
+            };
+            if let Some(err) = &plugin.error {
+                bail!("The plugin `{name}` is rejected: {err}");
+            }
+            // The disabled plugin can still be tried here.
+            plugin.enabled = true;
+            let file = file.canonicalize().with_context(|| format!("No found the file `{}`.", file.display()))?;
+            let library = match config.libraries.iter().find(|library| file.starts_with(&library.path)) {
+                Some(library) => library.clone(),
+                None => LibraryInfo {
+                    title: "Debug".to_owned(),
+                    path: file.parent().unwrap_or(Path::new("/")).to_path_buf(),
+                },
+            };
+            let mut media = LibrarySystem::read_media(config.clone(), &library, 1, file).await?;
+            println!("Before:\n{}", serde_json::to_string_pretty(&media)?);
+            let mut context = PluginsContext::new(&[plugin]).await;
+            context.process_media_info_json(&mut media).await;
+            println!("After:\n{}", serde_json::to_string_pretty(&media)?);
+        }
+    }
+    Ok(())
+}
+
+async fn database(command: DbCommands, config: Arc<Config>) -> Result<()> {
+    // Restore must not open the database it is going to replace.
+    if let DbCommands::Restore { path } = command {
+        let Some(data_path) = &config.data_path else {
+            bail!("The `data_path` must be settled, the database lives in it.");
+        };
+        let kept = backup::restore(&path, data_path).await?;
+        println!("Restored the database from `{}`, the replaced one is kept as `{}`.", path.display(), kept.display());
+        return Ok(());
+    }
+    let db = open_db(&config).await?;
+    // Create or migrate the tables before touching them.
+    let shared = SharedConfig::new(config.as_ref().clone());
+    UserSystem::new(db.clone()).await?;
+    ShareSystem::new(db.clone()).await?;
+    PluginSystem::new(db.clone(), shared.clone()).await?;
+    LibrarySystem::new(db.clone(), shared).await?;
+    db::stamp_schema_version(&db).await?;
+    match command {
+        DbCommands::Export { path } => {
+            let tables = db::export_tables(&db, &EXPORT_TABLES).await?;
+            let total: usize = tables.values().filter_map(Value::as_array).map(Vec::len).sum();
+            let dump = json!({ "version": EXPORT_VERSION, "tables": tables });
+            fs::write(&path, serde_json::to_vec_pretty(&dump)?)
+                .await
+                .with_context(|| format!("Write `{}` failed!", path.display()))?;
+            println!("Exported {total} rows into `{}`.", path.display());
+        }
+        DbCommands::Import { path } => {
+            let content = fs::read(&path).await.with_context(|| format!("Read `{}` failed!", path.display()))?;
+            let dump: Value = serde_json::from_slice(&content).with_context(|| "Deserialize the dump failed!")?;
+            let version = dump.get("version").and_then(Value::as_u64);
+            if version != Some(EXPORT_VERSION) {
+                bail!("Unsupported dump version {version:?}, expected {EXPORT_VERSION}.");
+            }
+            let Some(tables) = dump.get("tables").and_then(Value::as_object) else {
+                bail!("Missing the `tables` of dump.");
+            };
+            let total = db::import_tables(&db, &EXPORT_TABLES, tables).await?;
+            println!("Imported {total} rows from `{}`.", path.display());
+        }
+        DbCommands::Backup { dir } => {
+            let Some(dir) = dir.or_else(|| config.backup_dir()) else {
+                bail!("No backup directory, pass `--dir` or set the `data_path`.");
+            };
+            let info = backup::backup(&db, &dir).await?;
+            if let Some(backup) = &config.backup {
+                backup::rotate(&dir, backup.retention).await?;
+            }
+            println!("Backup `{}` ({} bytes) is taken into `{}`.", info.name, info.size, dir.display());
+        }
+        DbCommands::Restore { .. } => unreachable!("Restored before opening the database."),
+    }
+    Ok(())
+}
+
+async fn healthcheck(config: &Config) -> Result<()> {
+    let Some(addr) = config.listen_addrs().into_iter().next() else {
+        bail!("No address is listened.");
+    };
+    let status = tokio::time::timeout(HEALTHCHECK_TIMEOUT, request_healthz(config, addr))
+        .await
+        .context("Health check timed out!")??;
+    if status != 200 {
+        bail!("Server is unhealthy, `/healthz` responded {status}.");
+    }
+    println!("Server is healthy.");
+    Ok(())
+}
+
+/// Return the status code of `/healthz`.
+async fn request_healthz(config: &Config, addr: ListenAddr) -> Result<u16> {
+    match addr {
+        ListenAddr::Tcp(addr) => {
+            // The server listening on all interfaces is reachable by the loopback.
+            let addr = match addr.rsplit_once(':') {
+                Some(("0.0.0.0", port)) => format!("127.0.0.1:{port}"),
+                Some(("[::]", port)) => format!("[::1]:{port}"),
+                _ => addr,
+            };
+            let scheme = if config.tls.is_some() { "https" } else { "http" };
+            // The certificate is issued for the public name, not the loopback address.
+            let client = reqwest::Client::builder().danger_accept_invalid_certs(true).build()?;
+            let response = client.get(format!("{scheme}://{addr}/healthz")).send().await?;
+            Ok(response.status().as_u16())
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let mut stream = tokio::net::UnixStream::connect(&path)
+                .await
+                .with_context(|| format!("Connect `{}` failed!", path.display()))?;
+            stream
+                .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await?;
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).await?;
+            String::from_utf8_lossy(&response)
+                .split_whitespace()
+                .nth(1)
+                .and_then(|status| status.parse().ok())
+                .context("Invalid response of `/healthz`.")
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(path) => bail!("Unix socket `{}` is not supported on this platform.", path.display()),
+    }
+}
@@ -1,15 +1,18 @@
 use std::{
     collections::HashSet,
     path::{Path, PathBuf},
+    sync::{Arc, RwLock},
 };
 
 use crate::{library_system::model::LibraryInfo, meta::Meta};
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use tokio::fs;
-use tracing::warn;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+pub mod watch;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Config {
     pub libraries: Vec<LibraryInfo>,
     pub data_path: Option<PathBuf>,
@@ -18,19 +21,409 @@ pub struct Config {
     pub port: u16,
     #[serde(default)]
     pub public_url: String,
+    /// Seconds the in-flight requests are allowed to finish after the shutdown signal.
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Prefix of all routes except `/metrics`, `/healthz` and `/readyz`, like `/diosic`.
+    /// The `public_url` should contain it.
+    #[serde(default)]
+    pub base_path: String,
+    /// Serve the web UI from this directory, in place of the embedded one.
+    #[serde(default)]
+    pub webpage_dir: Option<PathBuf>,
+    /// Addresses listened in place of `host` and `port`: `host:port`, `[ipv6]:port` or `unix:/path/of/socket`.
+    /// Only available in config file.
+    #[serde(default)]
+    pub listen: Vec<String>,
+    /// Serve HTTPS on the TCP addresses, only available in config file.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Allow any origin if it is `None`, only available in config file.
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    /// Single sign-on by OpenID Connect, only available in config file.
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    /// Trust the user authenticated by reverse proxy, only available in config file.
+    #[serde(default)]
+    pub proxy_auth: Option<ProxyAuthConfig>,
+    /// Backups of the database, only available in config file.
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+    /// Serve the Prometheus metrics on `/metrics`, it's disabled if `None`, only available in config file.
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+}
+
+/// Address the server listens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TlsConfig {
+    /// PEM file of the certificate chain, reloaded once it's modified.
+    pub cert: PathBuf,
+    /// PEM file of the private key, reloaded once it's modified.
+    pub key: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CorsConfig {
+    /// Origins like `https://example.com`, `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Allow the cookies and credentials, can't be used with the origin `*`.
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// Seconds the preflight response can be cached.
+    pub max_age: Option<usize>,
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    ["GET", "POST", "PUT", "DELETE"].map(str::to_owned).to_vec()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BackupConfig {
+    /// Defaults to `{data_path}/backups`.
+    pub dir: Option<PathBuf>,
+    /// Take a backup every these hours, no scheduled backup if it is `None`.
+    pub interval_hours: Option<u64>,
+    /// Keep the newest backups of this count, the older ones are deleted.
+    #[serde(default = "default_backup_retention")]
+    pub retention: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MetricsConfig {
+    /// Scrapers must send it by `Authorization: Bearer <token>`, the metrics are public if it is `None`.
+    pub bearer_token: Option<String>,
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
+}
+
+fn default_backup_retention() -> usize {
+    7
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProxyAuthConfig {
+    /// Header contains the username.
+    #[serde(default = "default_proxy_auth_header")]
+    pub header: String,
+    /// The header is only trusted if request come from these CIDRs,
+    /// their `X-Forwarded-For` is also followed to find the client address.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpNet>,
+    /// Trust the requests come from the unix socket listeners, they have no address to match the CIDRs.
+    #[serde(default)]
+    pub trust_unix_socket: bool,
+    /// Create the user with this role at first sight, unknown users are rejected if it is `None`.
+    pub create_role: Option<String>,
 }
 
+fn default_proxy_auth_header() -> String {
+    "Remote-User".to_owned()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct OidcConfig {
+    /// Issuer of provider, `{issuer_url}/.well-known/openid-configuration` must be reachable.
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Defaults to `{public_url}/api/oidc/callback`.
+    pub redirect_url: Option<String>,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    /// Claim used as the username of provisioned user.
+    #[serde(default = "default_oidc_username_claim")]
+    pub username_claim: String,
+    /// Claim contains the groups of user, can be an array or a string.
+    #[serde(default = "default_oidc_groups_claim")]
+    pub groups_claim: String,
+    /// Users in this group become admin.
+    pub admin_group: Option<String>,
+    /// Users in this group become guest.
+    pub guest_group: Option<String>,
+    /// Only users in this group can login if it is settled.
+    pub allowed_group: Option<String>,
+    /// Accept the id token signed by the client secret (`HS256`, `HS384` or `HS512`) if the provider supports it,
+    /// only for the providers can't sign by keys.
+    #[serde(default)]
+    pub allow_hs_id_token: bool,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["openid".to_owned(), "profile".to_owned(), "email".to_owned()]
+}
+
+fn default_oidc_username_claim() -> String {
+    "preferred_username".to_owned()
+}
+
+fn default_oidc_groups_claim() -> String {
+    "groups".to_owned()
+}
+
+/// Config can be replaced at runtime, readers take the snapshot by [`SharedConfig::get`].
+#[derive(Debug, Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.0.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// Replace the config, return the old one.
+    pub fn replace(&self, config: Config) -> Arc<Config> {
+        let mut current = self.0.write().unwrap_or_else(|err| err.into_inner());
+        std::mem::replace(&mut *current, Arc::new(config))
+    }
+}
+
+/// Prefix of the environment variables layered over the config file.
+pub const ENV_PREFIX: &str = "DIOSIC_";
+
 impl Config {
-    pub async fn load_from_path(path: &PathBuf) -> Result<Config> {
-        if path.is_file() {
-            let content = fs::read_to_string(path).await?;
-            let config = serde_json::from_str::<Config>(&content)
-                .with_context(|| "Deserialize config content failed!")?;
-            config.clears().await?;
-            Ok(config)
+    /// Resolve the config from the file or the command line, then layer the environment variables and validate it.
+    pub async fn load(meta: &Meta) -> Result<Config> {
+        match &meta.config {
+            Some(path) => Self::load_file(path).await,
+            None => {
+                let config = Self::load_from_meta(meta)?;
+                config.validate()?;
+                Ok(config)
+            }
+        }
+    }
+
+    /// Load the config file with the environment variables layered, then validate it.
+    pub async fn load_file(path: &Path) -> Result<Config> {
+        let mut config = Self::load_from_path(path).await?;
+        config.apply_env(std::env::vars())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load the config file, the format is chosen by the extension of `path`.
+    pub async fn load_from_path(path: &Path) -> Result<Config> {
+        if !path.is_file() {
+            bail!("The config path `{}` is not a file.", path.display());
+        }
+        let content = fs::read_to_string(path).await?;
+        let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+        let config = match extension.to_ascii_lowercase().as_str() {
+            "toml" => toml::from_str::<Config>(&content).map_err(anyhow::Error::from),
+            "json" => serde_json::from_str::<Config>(&content).map_err(anyhow::Error::from),
+            "yaml" | "yml" => serde_yaml::from_str::<Config>(&content).map_err(anyhow::Error::from),
+            _ => bail!("Unsupported config format `{extension}`, expected `toml`, `json`, `yaml` or `yml`."),
+        };
+        config.with_context(|| format!("Deserialize config `{}` failed!", path.display()))
+    }
+
+    pub fn load_from_meta(meta: &Meta) -> Result<Self> {
+        let libraries = match &meta.library {
+            Some(libraries) => to_libraries(libraries)?,
+            None => match std::env::var(format!("{ENV_PREFIX}LIBRARIES")) {
+                Ok(libraries) => to_libraries(&split_env_list(&libraries))?,
+                Err(_) => vec![],
+            },
+        };
+        Ok(Self {
+            libraries,
+            covers_cached_path: meta.data_path.clone().map(|p| p.join("covers_cached")),
+            data_path: meta.data_path.clone(),
+            host: meta.host.clone(),
+            port: meta.port,
+            public_url: meta.public_url.clone(),
+            shutdown_timeout: meta.shutdown_timeout,
+            base_path: meta.base_path.clone(),
+            webpage_dir: meta.webpage_dir.clone(),
+            listen: vec![],
+            tls: None,
+            cors: None,
+            oidc: None,
+            proxy_auth: None,
+            backup: None,
+            metrics: None,
+        })
+    }
+
+    /// Override the fields by the `DIOSIC_*` variables in `vars`.
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        for (key, value) in vars {
+            let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            match name {
+                "HOST" => self.host = value,
+                "PORT" => {
+                    self.port = value
+                        .parse()
+                        .with_context(|| format!("`{key}` must be a port number, found `{value}`."))?
+                }
+                "PUBLIC_URL" => self.public_url = value,
+                "BASE_PATH" => self.base_path = value,
+                "WEBPAGE_DIR" => self.webpage_dir = Some(PathBuf::from(value)),
+                "SHUTDOWN_TIMEOUT" => {
+                    self.shutdown_timeout = value
+                        .parse()
+                        .with_context(|| format!("`{key}` must be the seconds, found `{value}`."))?
+                }
+                "DATA_PATH" => self.data_path = Some(PathBuf::from(value)),
+                "COVERS_CACHED_PATH" => self.covers_cached_path = Some(PathBuf::from(value)),
+                "LIBRARIES" => self.libraries = to_libraries(&split_env_list(&value))?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the config, all problems are reported together.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+        let mut titles = HashSet::with_capacity(self.libraries.len());
+        for library in &self.libraries {
+            if library.title.trim().is_empty() {
+                problems.push(format!("Library with path `{}` has an empty title.", library.path.display()));
+            } else if !titles.insert(library.title.as_str()) {
+                problems.push(format!("Library title `{}` is duplicated.", library.title));
+            }
+            if !library.path.is_dir() {
+                problems.push(format!("Library `{}` path `{}` is not a directory.", library.title, library.path.display()));
+            }
+        }
+        if self.port == 0 {
+            problems.push("Port must be between 1 and 65535.".to_owned());
+        }
+        if self.host.trim().is_empty() {
+            problems.push("Host can't be empty.".to_owned());
+        }
+        if let Some(data_path) = &self.data_path {
+            if !data_path.is_dir() {
+                problems.push(format!("Data path `{}` is not a directory.", data_path.display()));
+            }
+        }
+        if !self.public_url.is_empty() && !self.public_url.starts_with("http://") && !self.public_url.starts_with("https://") {
+            problems.push(format!("Public url `{}` must start with `http://` or `https://`.", self.public_url));
+        }
+        if !self.base_path.is_empty() && (!self.base_path.starts_with('/') || self.base_path.ends_with('/')) {
+            problems.push(format!("Base path `{}` must start with `/` and not end with `/`.", self.base_path));
+        }
+        if let Some(webpage_dir) = &self.webpage_dir {
+            if !webpage_dir.is_dir() {
+                problems.push(format!("Webpage directory `{}` is not a directory.", webpage_dir.display()));
+            }
+        }
+        for addr in self.listen_addrs() {
+            match addr {
+                ListenAddr::Tcp(addr) => {
+                    if addr.rsplit_once(':').and_then(|(_, port)| port.parse::<u16>().ok()).is_none() {
+                        problems.push(format!("Listen address `{addr}` must be `host:port`."));
+                    }
+                }
+                ListenAddr::Unix(path) => {
+                    if cfg!(not(unix)) {
+                        problems.push(format!("Unix socket `{}` is not supported on this platform.", path.display()));
+                    }
+                }
+            }
+        }
+        if let Some(tls) = &self.tls {
+            for (name, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.is_file() {
+                    problems.push(format!("`tls.{name}` `{}` is not a file.", path.display()));
+                }
+            }
+        }
+        if let Some(cors) = &self.cors {
+            for origin in &cors.allowed_origins {
+                if origin != "*" && !origin.starts_with("http://") && !origin.starts_with("https://") {
+                    problems.push(format!("CORS origin `{origin}` must be `*` or start with `http://` or `https://`."));
+                }
+            }
+            if cors.allow_credentials && cors.allowed_origins.iter().any(|origin| origin == "*") {
+                problems.push("`cors.allow_credentials` can't be used with the origin `*`.".to_owned());
+            }
+            for method in &cors.allowed_methods {
+                if actix_web::http::Method::from_bytes(method.as_bytes()).is_err() {
+                    problems.push(format!("CORS method `{method}` is invalid."));
+                }
+            }
+        }
+        if self.proxy_auth.as_ref().is_some_and(|proxy| proxy.trusted_proxies.is_empty() && !proxy.trust_unix_socket) {
+            problems.push("`proxy_auth.trusted_proxies` can't be empty unless `proxy_auth.trust_unix_socket` is enabled.".to_owned());
+        }
+        if self.proxy_auth.as_ref().is_some_and(|proxy| proxy.trust_unix_socket)
+            && !self.listen_addrs().iter().any(|addr| matches!(addr, ListenAddr::Unix(_)))
+        {
+            problems.push("`proxy_auth.trust_unix_socket` is enabled but no unix socket is listened.".to_owned());
+        }
+        if let Some(backup) = &self.backup {
+            if backup.interval_hours == Some(0) {
+                problems.push("`backup.interval_hours` must be more than 0.".to_owned());
+            }
+            if backup.retention == 0 {
+                problems.push("`backup.retention` must be more than 0.".to_owned());
+            }
+            if self.backup_dir().is_none() {
+                problems.push("`backup.dir` or `data_path` must be settled to take backups.".to_owned());
+            }
+        }
+        if self.metrics.as_ref().is_some_and(|metrics| metrics.bearer_token.as_ref().is_some_and(|token| token.is_empty())) {
+            problems.push("`metrics.bearer_token` can't be empty.".to_owned());
+        }
+        if problems.is_empty() {
+            Ok(())
         } else {
-            panic!("The `config_path` is not the file.");
+            bail!("Invalid config:\n  - {}", problems.join("\n  - "))
+        }
+    }
+
+    /// Addresses of `listen`, or `host:port` if it is empty.
+    pub fn listen_addrs(&self) -> Vec<ListenAddr> {
+        if self.listen.is_empty() {
+            let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+            return vec![ListenAddr::Tcp(format!("{host}:{}", self.port))];
         }
+        self.listen
+            .iter()
+            .map(|addr| match addr.strip_prefix("unix:") {
+                Some(path) => ListenAddr::Unix(PathBuf::from(path)),
+                None => ListenAddr::Tcp(addr.clone()),
+            })
+            .collect()
+    }
+
+    /// Directory the backups of database are written into.
+    pub fn backup_dir(&self) -> Option<PathBuf> {
+        match self.backup.as_ref().and_then(|backup| backup.dir.clone()) {
+            Some(dir) => Some(dir),
+            None => self.data_path.as_ref().map(|p| p.join("backups")),
+        }
+    }
+
+    /// Render the config as TOML with the secrets hidden.
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        let mut config = self.clone();
+        if let Some(oidc) = config.oidc.as_mut() {
+            oidc.client_secret = "<redacted>".to_owned();
+        }
+        if let Some(token) = config.metrics.as_mut().and_then(|metrics| metrics.bearer_token.as_mut()) {
+            *token = "<redacted>".to_owned();
+        }
+        Ok(toml::to_string_pretty(&config)?)
     }
 
     pub async fn clears(&self) -> Result<()> {
@@ -46,78 +439,35 @@ impl Config {
         }
         Ok(())
     }
+}
 
-    pub async fn load_from_meta(meta: Meta) -> Result<Self> {
-        let config = Self {
-            libraries: to_libraries(meta.library.as_ref().unwrap_or(&vec![])),
-            covers_cached_path: meta.data_path.clone().map(|p| p.join("covers_cached")),
-            data_path: meta.data_path,
-            host: meta.host,
-            port: meta.port,
-            public_url: meta.public_url,
-        };
-        config.clears().await?;
-        Ok(config)
-    }
+/// Split the list of `DIOSIC_LIBRARIES`, entries are separated by `,`.
+fn split_env_list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|v| !v.is_empty()).map(str::to_owned).collect()
 }
 
-fn to_libraries(title_with_paths: &Vec<String>) -> Vec<LibraryInfo> {
+/// Parse the `library_title;library_dir_path` or `library_dir_path` options.
+fn to_libraries(title_with_paths: &[String]) -> Result<Vec<LibraryInfo>> {
     let mut libraries = Vec::with_capacity(title_with_paths.len());
     let mut count_unknown = 1;
-    let mut contain_names = HashSet::with_capacity(title_with_paths.len());
 
     for tp in title_with_paths {
-        let tp: Vec<&str> = tp.split(";").collect();
-        match tp.len() {
-            0 => {
-                panic!("Please ensure at least one library is settled.")
-            }
-            1 => {
-                let title = format!("Unknown_{}", count_unknown);
-                let path = Path::new(tp[0]).to_path_buf();
-
-                if contain_names.contains(&title) {
-                    warn!(
-                        "Can't import library `{}` because already contain target library name.",
-                        title
-                    )
-                } else if path.is_dir() {
-                    contain_names.insert(title.to_owned());
-                    libraries.push(LibraryInfo { title, path });
-                    count_unknown += 1;
-                } else {
-                    warn!(
-                        "Can't import library `{}` with path `{}` because it is not directory.",
-                        title,
-                        path.to_str().unwrap()
-                    )
-                }
-            }
-            2 => {
-                let title = tp[0].to_owned();
-                let path = Path::new(tp[1]).to_path_buf();
-
-                if contain_names.contains(&title) {
-                    warn!(
-                        "Can't import library `{}` because already contain target library name.",
-                        title
-                    )
-                } else if path.is_dir() {
-                    contain_names.insert(title.to_owned());
-                    libraries.push(LibraryInfo { title, path });
-                } else {
-                    warn!(
-                        "Can't import library `{}` with path `{}` because it not directory.",
-                        title,
-                        path.to_str().unwrap()
-                    )
-                }
-            }
-            _ => {
-                panic!("Please ensure format `media` option is `library_title;library_dir_path`")
+        let tp: Vec<&str> = tp.split(';').collect();
+        match tp.as_slice() {
+            [path] => {
+                libraries.push(LibraryInfo {
+                    title: format!("Unknown_{}", count_unknown),
+                    path: Path::new(path).to_path_buf(),
+                });
+                count_unknown += 1;
             }
+            [title, path] => libraries.push(LibraryInfo {
+                title: (*title).to_owned(),
+                path: Path::new(path).to_path_buf(),
+            }),
+            _ => bail!("Please ensure format of library option is `library_title;library_dir_path`, found `{}`.", tp.join(";")),
         }
     }
 
-    libraries
+    Ok(libraries)
 }
@@ -0,0 +1,121 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use tracing::{error, info, warn};
+
+use super::{Config, SharedConfig};
+use crate::{
+    library_system::{model::LibraryInfo, LibrarySystem},
+    plugin_system::PluginSystem,
+};
+
+/// How often the modified time of config file is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Reload the config when the file is modified or the process receives `SIGHUP`.
+pub async fn watch(path: PathBuf, config: SharedConfig, library_system: LibrarySystem, plugin_system: PluginSystem) {
+    let (hangup_tx, mut hangup_rx) = tokio::sync::mpsc::channel::<()>(1);
+    #[cfg(unix)]
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    let _ = hangup_tx.try_send(());
+                }
+            });
+        }
+        Err(err) => error!("Listen the `SIGHUP` failed: {err}"),
+    }
+    #[cfg(not(unix))]
+    drop(hangup_tx);
+
+    info!("Watching the config file `{}`.", path.display());
+    let mut modified = modified_time(&path);
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let current = modified_time(&path);
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                info!("Config file is modified, reloading..");
+            }
+            Some(()) = hangup_rx.recv() => {
+                modified = modified_time(&path);
+                info!("Received `SIGHUP`, reloading the config..");
+            }
+        }
+        reload(&path, &config, &library_system, &plugin_system).await;
+    }
+}
+
+/// Apply the config file at runtime, the current config is kept if the file is invalid.
+pub async fn reload(path: &Path, config: &SharedConfig, library_system: &LibrarySystem, plugin_system: &PluginSystem) {
+    let mut new = match Config::load_file(path).await {
+        Ok(new) => new,
+        Err(err) => {
+            error!("Reload config failed, the current config is kept! {err:#}");
+            return;
+        }
+    };
+    let old = config.get();
+    keep_restart_required(&old, &mut new);
+    if *old == new {
+        info!("Config is not changed.");
+        return;
+    }
+
+    let (changed, removed) = diff_libraries(&old.libraries, &new.libraries);
+    let public_url = (old.public_url != new.public_url).then(|| new.public_url.clone());
+    if public_url.is_some() && new.oidc.as_ref().is_some_and(|oidc| oidc.redirect_url.is_none()) {
+        warn!("The single sign-on keeps the redirect url of old `public_url` until restart.");
+    }
+    config.replace(new);
+    info!("Config reloaded.");
+
+    if let Some(public_url) = public_url {
+        match library_system.update_public_url(&public_url).await {
+            Ok(count) => info!("Updated the cover url of {count} medias."),
+            Err(err) => error!("Update the cover url of medias failed: {err}"),
+        }
+    }
+    if !changed.is_empty() || !removed.is_empty() {
+        if let Err(err) = library_system.reload_libraries(plugin_system, &changed, &removed).await {
+            error!("Rescan the changed libraries failed: {err}");
+        }
+    }
+}
+
+/// Settings only read at startup are restored from the running config with a warning.
+fn keep_restart_required(old: &Config, new: &mut Config) {
+    macro_rules! keep {
+        ($($field:ident),*) => {
+            $(
+                if new.$field != old.$field {
+                    warn!(concat!("`", stringify!($field), "` is changed, restart to apply it."));
+                    new.$field = old.$field.clone();
+                }
+            )*
+        };
+    }
+    keep!(host, port, data_path, covers_cached_path, shutdown_timeout, base_path, webpage_dir, listen, tls, cors, oidc);
+}
+
+/// Return the libraries added or changed in `new`, and the titles of libraries removed from `old`.
+fn diff_libraries(old: &[LibraryInfo], new: &[LibraryInfo]) -> (Vec<LibraryInfo>, Vec<String>) {
+    let changed = new.iter().filter(|library| !old.contains(library)).cloned().collect();
+    let removed = old
+        .iter()
+        .filter(|library| !new.iter().any(|v| v.title == library.title))
+        .map(|library| library.title.clone())
+        .collect();
+    (changed, removed)
+}
@@ -1,19 +1,45 @@
 use std::{path::Path, sync::Arc, time::Duration};
 
 use crate::config::Config;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde_json::{Map, Value};
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqlitePoolOptions},
-    Pool, Sqlite,
+    Column, Pool, Row, Sqlite, TypeInfo, ValueRef,
 };
+use thiserror::Error;
 use tracing::warn;
 
+pub mod backup;
+
+pub const DB_FILE_NAME: &str = "diosic.db";
+/// Version of the schema stored in `PRAGMA user_version`, increase it once the tables are changed incompatibly.
+/// It's stamped only after the migrations of all systems succeeded.
+pub const SCHEMA_VERSION: i64 = 1;
+/// The oldest version the migrations of systems can upgrade, `0` is the database before the versioning.
+pub const MIN_SCHEMA_VERSION: i64 = 0;
+
+/// Error of the queries, handlers map it into the API error instead of panicking.
+#[derive(Debug, Error)]
+pub enum DbError {
+    #[error("No found the {0}.")]
+    NotFound(&'static str),
+    #[error("Database error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("Serialize the column failed: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Cancelled by the shutdown.")]
+    Cancelled,
+}
+
+pub type DbResult<T> = std::result::Result<T, DbError>;
+
 pub async fn init(config: Arc<Config>) -> Result<Pool<Sqlite>> {
     let options = config.data_path.as_ref().map(Path::new).and_then(|p| {
         if p.is_dir() {
             Some(
                 SqliteConnectOptions::new()
-                    .filename(p.join("diosic.db"))
+                    .filename(p.join(DB_FILE_NAME))
                     .busy_timeout(Duration::from_millis(6000))
                     .create_if_missing(true),
             )
@@ -22,11 +48,133 @@ pub async fn init(config: Arc<Config>) -> Result<Pool<Sqlite>> {
         }
     });
     let pool_options = SqlitePoolOptions::new().max_connections(12);
-    Ok(match options {
+    let db = match options {
         Some(options) => pool_options.connect_with(options).await?,
         None => {
             warn!("Don't exists the data path. Will use memory database.");
             pool_options.connect("sqlite::memory:").await?
         }
-    })
+    };
+    check_schema_version(schema_version(&db).await?)?;
+    Ok(db)
+}
+
+/// Reject the version the migrations of systems can't upgrade.
+pub fn check_schema_version(version: i64) -> Result<()> {
+    if version > SCHEMA_VERSION {
+        bail!("The database has schema version {version}, newer than {SCHEMA_VERSION} of this version, please upgrade first.");
+    }
+    if version < MIN_SCHEMA_VERSION {
+        bail!("The database has schema version {version}, older than {MIN_SCHEMA_VERSION} which this version can migrate.");
+    }
+    Ok(())
+}
+
+pub async fn schema_version(db: &Pool<Sqlite>) -> Result<i64> {
+    Ok(sqlx::query("PRAGMA user_version").fetch_one(db).await?.try_get(0)?)
+}
+
+/// Mark the database at [`SCHEMA_VERSION`], call it only after the tables of all systems are created or migrated.
+pub async fn stamp_schema_version(db: &Pool<Sqlite>) -> Result<()> {
+    sqlx::query(&format!("PRAGMA user_version = {SCHEMA_VERSION}")).execute(db).await?;
+    Ok(())
+}
+
+/// Add the column into the table created by older version if it is missing.
+/// Return `true` if the column is added.
+pub async fn add_column_if_missing(db: &Pool<Sqlite>, table: &str, column: &str, definition: &str) -> Result<bool> {
+    let exists = sqlx::query(&format!("PRAGMA table_info({table})"))
+        .fetch_all(db)
+        .await?
+        .iter()
+        .any(|row| row.get::<String, _>("name") == column);
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"))
+            .execute(db)
+            .await?;
+    }
+    Ok(!exists)
+}
+
+async fn table_columns(db: &Pool<Sqlite>, table: &str) -> Result<Vec<String>> {
+    let rows = sqlx::query(&format!("PRAGMA table_info({table})")).fetch_all(db).await?;
+    Ok(rows.iter().map(|row| row.get::<String, _>("name")).collect())
+}
+
+/// Dump the rows of tables as JSON objects.
+pub async fn export_tables(db: &Pool<Sqlite>, tables: &[&str]) -> Result<Map<String, Value>> {
+    let mut result = Map::new();
+    for table in tables {
+        let rows = sqlx::query(&format!("SELECT * FROM {table}")).fetch_all(db).await?;
+        let mut items = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut item = Map::new();
+            for (i, column) in row.columns().iter().enumerate() {
+                let raw = row.try_get_raw(i)?;
+                let value = if raw.is_null() {
+                    Value::Null
+                } else {
+                    match raw.type_info().name() {
+                        "INTEGER" => Value::from(row.try_get::<i64, _>(i)?),
+                        "REAL" => Value::from(row.try_get::<f64, _>(i)?),
+                        "TEXT" => Value::from(row.try_get::<String, _>(i)?),
+                        other => bail!("Unsupported type `{other}` of `{table}.{}`.", column.name()),
+                    }
+                };
+                item.insert(column.name().to_owned(), value);
+            }
+            items.push(Value::Object(item));
+        }
+        result.insert((*table).to_owned(), Value::Array(items));
+    }
+    Ok(result)
+}
+
+/// Replace the rows of tables by the dump of [`export_tables`], all or nothing is imported.
+/// Return the count of imported rows.
+pub async fn import_tables(db: &Pool<Sqlite>, tables: &[&str], dump: &Map<String, Value>) -> Result<usize> {
+    if let Some(unknown) = dump.keys().find(|table| !tables.contains(&table.as_str())) {
+        bail!("Unknown table `{unknown}` in the dump.");
+    }
+    let mut columns = Vec::with_capacity(tables.len());
+    for table in tables {
+        columns.push(table_columns(db, table).await?);
+    }
+    let mut tx = db.begin().await?;
+    let mut total = 0;
+    for (table, columns) in tables.iter().zip(columns) {
+        let Some(items) = dump.get(*table) else {
+            continue;
+        };
+        let Some(items) = items.as_array() else {
+            bail!("Rows of `{table}` must be an array.");
+        };
+        sqlx::query(&format!("DELETE FROM {table}")).execute(&mut *tx).await?;
+        for item in items {
+            let Some(item) = item.as_object() else {
+                bail!("Row of `{table}` must be an object.");
+            };
+            if let Some(unknown) = item.keys().find(|column| !columns.contains(column)) {
+                bail!("Unknown column `{table}.{unknown}` in the dump.");
+            }
+            let mut builder = sqlx::QueryBuilder::<Sqlite>::new(format!("INSERT INTO {table} ("));
+            builder.push(item.keys().cloned().collect::<Vec<_>>().join(", ")).push(") VALUES (");
+            let mut separated = builder.separated(", ");
+            for value in item.values() {
+                match value {
+                    Value::Null => separated.push_bind(None::<String>),
+                    Value::Bool(v) => separated.push_bind(*v),
+                    Value::Number(v) if v.is_i64() => separated.push_bind(v.as_i64()),
+                    Value::Number(v) => separated.push_bind(v.as_f64()),
+                    Value::String(v) => separated.push_bind(v.clone()),
+                    _ => bail!("Unsupported value `{value}` in `{table}`."),
+                };
+            }
+            separated.push_unseparated(")");
+            builder.build().execute(&mut *tx).await?;
+            total += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(total)
 }
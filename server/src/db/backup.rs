@@ -0,0 +1,183 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, Connection, Pool, Row, Sqlite};
+use tokio::fs;
+use tracing::{error, info};
+use utoipa::ToSchema;
+
+use super::{check_schema_version, DB_FILE_NAME};
+use crate::config::SharedConfig;
+
+const BACKUP_PREFIX: &str = "diosic-";
+const BACKUP_EXTENSION: &str = ".db";
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BackupInfo {
+    pub name: String,
+    pub size: u64,
+    /// Unix timestamp in seconds.
+    pub created_at: i64,
+}
+
+/// Return `true` if `name` is a file name of backup, it never contains a path separator.
+pub fn is_backup_name(name: &str) -> bool {
+    name.strip_prefix(BACKUP_PREFIX)
+        .and_then(|v| v.strip_suffix(BACKUP_EXTENSION))
+        .is_some_and(|v| !v.is_empty() && v.chars().all(|c| c.is_ascii_digit() || c == '-'))
+}
+
+/// Take a consistent backup of the running database into `dir`.
+pub async fn backup(db: &Pool<Sqlite>, dir: &Path) -> Result<BackupInfo> {
+    fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Create the backup directory `{}` failed!", dir.display()))?;
+    let now = chrono::Local::now();
+    let name = format!("{BACKUP_PREFIX}{}{BACKUP_EXTENSION}", now.format("%Y%m%d-%H%M%S-%3f"));
+    let path = dir.join(&name);
+    sqlx::query("VACUUM INTO ?")
+        .bind(path.to_string_lossy())
+        .execute(db)
+        .await
+        .with_context(|| format!("Backup the database into `{}` failed!", path.display()))?;
+    Ok(BackupInfo {
+        size: fs::metadata(&path).await?.len(),
+        created_at: now.timestamp(),
+        name,
+    })
+}
+
+/// Backups in `dir`, the newest first.
+pub async fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>> {
+    let mut backups = vec![];
+    if !dir.is_dir() {
+        return Ok(backups);
+    }
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !is_backup_name(&name) {
+            continue;
+        }
+        let meta = entry.metadata().await?;
+        let created_at = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|time| time.as_secs() as i64)
+            .unwrap_or_default();
+        backups.push(BackupInfo {
+            name,
+            size: meta.len(),
+            created_at,
+        });
+    }
+    // The names contain the time, so they are sorted by time.
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+/// Delete the backups older than the newest `retention` ones, return the count of deleted.
+pub async fn rotate(dir: &Path, retention: usize) -> Result<usize> {
+    let backups = list_backups(dir).await?;
+    let mut deleted = 0;
+    for backup in backups.iter().skip(retention) {
+        fs::remove_file(dir.join(&backup.name)).await?;
+        deleted += 1;
+    }
+    Ok(deleted)
+}
+
+/// Check the file is a healthy database this version can open, return its schema version.
+pub async fn validate(path: &Path) -> Result<i64> {
+    if !path.is_file() {
+        bail!("The backup `{}` is not a file.", path.display());
+    }
+    let mut conn = SqliteConnectOptions::new()
+        .filename(path)
+        .read_only(true)
+        .connect()
+        .await
+        .with_context(|| format!("Open `{}` failed!", path.display()))?;
+    let integrity: String = sqlx::query("PRAGMA integrity_check")
+        .fetch_one(&mut conn)
+        .await
+        .with_context(|| format!("`{}` is not a database", path.display()))?
+        .try_get(0)?;
+    if integrity != "ok" {
+        bail!("Integrity check of `{}` failed: {integrity}", path.display());
+    }
+    let version: i64 = sqlx::query("PRAGMA user_version").fetch_one(&mut conn).await?.try_get(0)?;
+    check_schema_version(version)?;
+    // The roles are created by the migrations, so the database before the versioning may miss them.
+    let tables: &[&str] = if version == 0 { &["users"] } else { &["users", "roles"] };
+    for table in tables {
+        let exists = sqlx::query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table)
+            .fetch_optional(&mut conn)
+            .await?
+            .is_some();
+        if !exists {
+            bail!("The backup is missing the table `{table}`.");
+        }
+    }
+    conn.close().await?;
+    Ok(version)
+}
+
+/// Swap the backup in as the database of `data_path`, the server must be stopped.
+/// The replaced database is kept beside as `diosic.db.before-restore-*`.
+pub async fn restore(backup: &Path, data_path: &Path) -> Result<PathBuf> {
+    let version = validate(backup).await?;
+    info!("The backup has schema version {version}, restoring..");
+    let target = data_path.join(DB_FILE_NAME);
+    let kept = data_path.join(format!("{DB_FILE_NAME}.before-restore-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    // Copy into the same directory first, so the swap is an atomic rename.
+    let staging = data_path.join(format!("{DB_FILE_NAME}.restoring"));
+    fs::copy(backup, &staging).await.with_context(|| "Copy the backup failed!")?;
+    if target.is_file() {
+        fs::rename(&target, &kept).await.with_context(|| "Keep the current database failed!")?;
+    }
+    // The journal of replaced database must not be applied to the restored one.
+    for suffix in ["-journal", "-wal", "-shm"] {
+        let path = data_path.join(format!("{DB_FILE_NAME}{suffix}"));
+        if path.is_file() {
+            fs::remove_file(&path).await?;
+        }
+    }
+    fs::rename(&staging, &target).await.with_context(|| "Swap the backup in failed!")?;
+    Ok(kept)
+}
+
+/// Take the backups by `backup.interval_hours` of the config, changes of config are picked up after the next backup.
+pub async fn schedule(db: Pool<Sqlite>, config: SharedConfig) {
+    loop {
+        let interval_hours = config.get().backup.as_ref().and_then(|backup| backup.interval_hours);
+        let Some(interval_hours) = interval_hours else {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            continue;
+        };
+        tokio::time::sleep(Duration::from_secs(interval_hours * 60 * 60)).await;
+
+        let config = config.get();
+        let (Some(dir), Some(backup_config)) = (config.backup_dir(), config.backup.as_ref()) else {
+            continue;
+        };
+        match backup(&db, &dir).await {
+            Ok(info) => info!("Scheduled backup `{}` is taken.", info.name),
+            Err(err) => {
+                error!("Scheduled backup failed! {err:#}");
+                continue;
+            }
+        }
+        match rotate(&dir, backup_config.retention).await {
+            Ok(0) => (),
+            Ok(deleted) => info!("Deleted {deleted} old backups."),
+            Err(err) => error!("Rotate the backups failed! {err:#}"),
+        }
+    }
+}
@@ -0,0 +1,11 @@
+pub mod cli;
+pub mod config;
+pub mod db;
+pub mod library_system;
+pub mod meta;
+pub mod metrics;
+pub mod myutil;
+pub mod plugin_system;
+pub mod server;
+pub mod share_system;
+pub mod user_system;
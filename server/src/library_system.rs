@@ -1,17 +1,25 @@
 use std::{
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time,
 };
 
 use futures_util::future::join_all;
-use sqlx::{sqlite::SqliteRow, Pool, QueryBuilder, Row, Sqlite};
-use tracing::{error, info};
+use sqlx::{sqlite::SqliteRow, Pool, QueryBuilder, Row, Sqlite, SqliteConnection};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
+pub mod archive;
 pub mod model;
 
 use crate::{
-    config::Config,
+    config::{Config, SharedConfig},
+    db::{DbError, DbResult},
+    metrics::METRICS,
     myutil::{self},
     plugin_system::{self},
 };
@@ -23,18 +31,33 @@ const SQLITE_LIMIT: usize = 999;
 #[derive(Debug, Clone)]
 pub struct LibrarySystem {
     db: Pool<Sqlite>,
-    config: Arc<Config>,
+    config: SharedConfig,
+    /// Held while the medias are saving, so the ids of concurrent scans never collide.
+    scan_lock: Arc<Mutex<()>>,
+    /// Set once a full scan saved the medias.
+    loaded: Arc<AtomicBool>,
+    /// Cancelled by the shutdown, the running scan is rolled back.
+    cancel: CancellationToken,
+}
+
+/// The saved medias replaced by the scanned ones.
+pub enum Replace<'a> {
+    All,
+    Libraries(&'a [String]),
 }
 
 impl LibrarySystem {
-    pub async fn recreate_tables(db: Pool<Sqlite>) {
+    pub async fn recreate_tables(conn: &mut SqliteConnection) -> DbResult<()> {
         sqlx::query(
             "DROP TABLE IF EXISTS medias;
             DROP TABLE IF EXISTS media_categories;",
         )
-        .execute(&db)
-        .await
-        .expect("LibrarySystem drop tables failed!");
+        .execute(&mut *conn)
+        .await?;
+        Self::create_tables(conn).await
+    }
+
+    async fn create_tables(conn: &mut SqliteConnection) -> DbResult<()> {
         sqlx::query(
             "CREATE TABLE IF NOT EXISTS medias(
             id INTEGER PRIMARY KEY,
@@ -62,9 +85,8 @@ impl LibrarySystem {
         CREATE INDEX IF NOT EXISTS mi_genre ON medias (genre);
         CREATE INDEX IF NOT EXISTS mi_year ON medias (year);",
         )
-        .execute(&db)
-        .await
-        .expect("Create `medias` table failed!");
+        .execute(&mut *conn)
+        .await?;
 
         sqlx::query(
             "CREATE TABLE IF NOT EXISTS media_categories(
@@ -74,21 +96,28 @@ impl LibrarySystem {
         CREATE INDEX IF NOT EXISTS mci_category_title ON media_categories (category_title);
         CREATE INDEX IF NOT EXISTS mci_media_id ON media_categories (media_id);",
         )
-        .execute(&db)
-        .await
-        .expect("Create `media_categories` table failed!");
+        .execute(&mut *conn)
+        .await?;
+        Ok(())
     }
 
-    pub async fn new<'a>(db: Pool<Sqlite>, config: Arc<Config>) -> LibrarySystem {
-        Self::recreate_tables(db.clone()).await;
-
-        LibrarySystem { config, db }
+    pub async fn new(db: Pool<Sqlite>, config: SharedConfig) -> DbResult<LibrarySystem> {
+        // The medias of last run are served until the first scan replaces them.
+        Self::create_tables(&mut *db.acquire().await?).await?;
+
+        Ok(LibrarySystem {
+            config,
+            db,
+            scan_lock: Arc::new(Mutex::new(())),
+            loaded: Arc::new(AtomicBool::new(false)),
+            cancel: CancellationToken::new(),
+        })
     }
 
-    pub async fn scan(&self) -> (Vec<(LibraryInfo, Vec<PathBuf>)>, usize) {
-        let mut library_paths: Vec<(LibraryInfo, Vec<PathBuf>)> = Vec::with_capacity(self.config.libraries.len());
+    pub async fn scan(&self, libraries: &[LibraryInfo]) -> (Vec<(LibraryInfo, Vec<PathBuf>)>, usize) {
+        let mut library_paths: Vec<(LibraryInfo, Vec<PathBuf>)> = Vec::with_capacity(libraries.len());
         let mut total_path = 0;
-        for lib in &self.config.libraries {
+        for lib in libraries {
             let files = lib.fetch().await;
             if files.is_empty() {
                 continue;
@@ -100,78 +129,94 @@ impl LibrarySystem {
         (library_paths, total_path)
     }
 
-    pub async fn perform_medias(&self, plgsys: &plugin_system::PluginSystem, library_paths: Vec<(LibraryInfo, Vec<PathBuf>)>, total_path: usize) {
+    /// Save the medias of scanned paths in place of the `replace` ones.
+    /// The saved medias are untouched until the scanned ones are written in one transaction, it's rolled back if the scan is cancelled.
+    pub async fn perform_medias(&self, plgsys: &plugin_system::PluginSystem, library_paths: Vec<(LibraryInfo, Vec<PathBuf>)>, total_path: usize, replace: Replace<'_>) -> DbResult<()> {
         let start = time::Instant::now();
-        Self::recreate_tables(self.db.clone()).await;
         let mut plugins_context = if plgsys.exists_plugins().await { Some(plgsys.init_plugins_context().await) } else { None };
 
-        let mut media_start_id = 0;
+        let mut media_start_id: i64 = match replace {
+            Replace::All => 0,
+            Replace::Libraries(_) => sqlx::query("SELECT COALESCE(MAX(id), 0) AS max_id FROM medias").fetch_one(&self.db).await?.try_get("max_id")?,
+        };
         let mut medias_handlers = Vec::with_capacity(total_path);
+        let config = self.config.get();
         info!("Performing the medias..");
         for (library, paths) in &library_paths {
             for path in paths {
-                let config = self.config.clone();
+                let config = config.clone();
                 let path = path.clone();
                 let library = library.clone();
                 media_start_id += 1;
                 let handler = tokio::spawn(async move {
-                    let mut meta = match MediaMetaInfo::read_from_path(&path, &config).await {
-                        Ok(v) => v,
+                    match Self::read_media(config, &library, media_start_id, path).await {
+                        Ok(info) => Some(info),
                         Err(err) => {
                             error!("Read media meta info failed: {:?}", err);
-                            return None;
+                            None
                         }
-                    };
-                    if let Some(path) = get_image_path_media(&path) {
-                        meta.cover = Some(path);
-                    };
-
-                    let info = MediaInfo::from_meta(meta, config.clone(), &library, media_start_id, path.clone());
-
-                    Some(info)
+                    }
                 });
                 medias_handlers.push(handler);
             }
         }
 
-        let mut have_category = false;
-        let mut medias: Vec<MediaInfo> = join_all(medias_handlers)
-            .await
-            .into_iter()
-            .filter(|item| item.as_ref().is_ok_and(|inner| inner.is_some()))
-            .map(|item| {
-                let media = item.unwrap().unwrap();
-                if !have_category && !media.categories.is_empty() {
-                    have_category = true;
-                }
-                media
-            })
-            .collect();
-        if medias.is_empty() {
-            return;
-        }
+        let abort_handles: Vec<_> = medias_handlers.iter().map(|handler| handler.abort_handle()).collect();
+        let results = tokio::select! {
+            results = join_all(medias_handlers) => results,
+            _ = self.cancel.cancelled() => {
+                abort_handles.iter().for_each(|handle| handle.abort());
+                warn!("The scan is cancelled, the saved medias are untouched.");
+                return Err(DbError::Cancelled);
+            }
+        };
+        let mut medias: Vec<MediaInfo> = results.into_iter().filter_map(|item| item.ok().flatten()).collect();
+        METRICS.scan_files_parsed.inc_by(medias.len() as u64);
+        METRICS.scan_files_failed.inc_by((total_path.saturating_sub(medias.len())) as u64);
 
         if let Some(ctx) = plugins_context.as_mut() {
             for media in &mut medias {
+                if self.cancel.is_cancelled() {
+                    warn!("The scan is cancelled, the saved medias are untouched.");
+                    return Err(DbError::Cancelled);
+                }
                 ctx.process_media_info_json(media).await;
             }
         }
 
-        if have_category {
-            for medias in medias.chunks(SQLITE_LIMIT) {
-                let mut b = QueryBuilder::new("INSERT INTO media_categories(category_title, media_id) VALUES ");
-                let mut s = b.separated(",");
-                for media in medias {
-                    for category in &media.categories {
-                        s.push("(").push_bind_unseparated(category).push_unseparated(",").push_bind_unseparated(media.id).push_unseparated(")");
-                    }
-                }
-                b.build().execute(&self.db).await.expect("Insert categories failed!");
+        let mut tx = self.db.begin().await?;
+        match replace {
+            Replace::All => Self::recreate_tables(&mut tx).await?,
+            Replace::Libraries(titles) if !titles.is_empty() => {
+                let mut builder = QueryBuilder::new("DELETE FROM media_categories WHERE media_id IN (SELECT id FROM medias WHERE");
+                push_libraries(&mut builder, titles);
+                builder.push(")").build().execute(&mut *tx).await?;
+                let mut builder = QueryBuilder::new("DELETE FROM medias WHERE");
+                push_libraries(&mut builder, titles);
+                builder.build().execute(&mut *tx).await?;
             }
+            Replace::Libraries(_) => (),
+        }
+
+        let categories: Vec<(&String, i64)> = medias.iter().flat_map(|media| media.categories.iter().map(|category| (category, media.id))).collect();
+        // Each row binds 2 values, a chunk of medias may have no category at all.
+        for categories in categories.chunks(SQLITE_LIMIT / 2) {
+            QueryBuilder::new("INSERT INTO media_categories(category_title, media_id) ")
+                .push_values(categories, |mut b, (category, media_id)| {
+                    b.push_bind(*category).push_bind(*media_id);
+                })
+                .build()
+                .execute(&mut *tx)
+                .await?;
         }
 
         let mut total_media = 0;
         for medias in medias.chunks(SQLITE_LIMIT) {
+            if self.cancel.is_cancelled() {
+                tx.rollback().await?;
+                warn!("The scan is cancelled, the saved medias are rolled back.");
+                return Err(DbError::Cancelled);
+            }
             let r = QueryBuilder::new("INSERT INTO medias(id, path, cover_path, cover_url, title, library, album, artist, genre, year, sample_rate, bit_depth, audio_bitrate, overall_bitrate, channels, duration_seconds, file_name, file_type) ").push_values(medias, |mut b, media| {
                 b.push_bind(media.id)
                     .push_bind(media.path.to_string_lossy())
@@ -188,61 +233,142 @@ impl LibrarySystem {
                     .push_bind(media.audio_bitrate)
                     .push_bind(media.overall_bitrate)
                     .push_bind(media.channels)
-                    .push_bind(media.duration_seconds as u32)
+                    .push_bind(media.duration_seconds)
                 .push_bind(&media.file_name)
             .push_bind(&media.file_type);
-            }).build().execute(&self.db).await.expect("Insert medias failed!");
+            }).build().execute(&mut *tx).await?;
             total_media += r.rows_affected();
         }
+        tx.commit().await?;
 
-        info!("{total_media} medias saved in {:.2}s", start.elapsed().as_secs_f32())
+        METRICS.scan_duration.observe(start.elapsed().as_secs_f64());
+        info!("{total_media} medias saved in {:.2}s", start.elapsed().as_secs_f32());
+        Ok(())
+    }
+
+    /// Read the media of `path` in the library, the cover beside the file take precedence.
+    pub async fn read_media(config: Arc<Config>, library: &LibraryInfo, id: i64, path: PathBuf) -> anyhow::Result<MediaInfo> {
+        let mut meta = MediaMetaInfo::read_from_path(&path, &config).await?;
+        if let Some(path) = get_image_path_media(&path) {
+            meta.cover = Some(path);
+        };
+        Ok(MediaInfo::from_meta(meta, config, library, id, path))
     }
 
     pub async fn reload(&self, plgsys: &plugin_system::PluginSystem) {
+        let _guard = self.scan_lock.lock().await;
         info!("Scanning all library..");
-        let (library_paths, total_path) = self.scan().await;
-        self.perform_medias(plgsys, library_paths, total_path).await;
+        let config = self.config.get();
+        let (library_paths, total_path) = self.scan(&config.libraries).await;
+        match self.perform_medias(plgsys, library_paths, total_path, Replace::All).await {
+            Ok(()) => self.loaded.store(true, Ordering::Release),
+            Err(err) => error!("Save the medias failed: {err}"),
+        }
+    }
+
+    /// Cancel the running and later scans, return after the running one is rolled back.
+    pub async fn shutdown(&self) {
+        self.cancel.cancel();
+        let _guard = self.scan_lock.lock().await;
+    }
+
+    /// Whether the medias of all libraries have been scanned once.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.load(Ordering::Acquire)
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scan_lock.try_lock().is_err()
+    }
+
+    /// Drop the medias of `removed` libraries and rescan the `libraries`, the other libraries are untouched.
+    pub async fn reload_libraries(&self, plgsys: &plugin_system::PluginSystem, libraries: &[LibraryInfo], removed: &[String]) -> DbResult<()> {
+        let _guard = self.scan_lock.lock().await;
+        let titles: Vec<String> = libraries.iter().map(|library| library.title.clone()).chain(removed.iter().cloned()).collect();
+        if !libraries.is_empty() {
+            info!("Scanning the libraries: {}", libraries.iter().map(|library| library.title.as_str()).collect::<Vec<_>>().join(", "));
+        }
+        let (library_paths, total_path) = self.scan(libraries).await;
+        self.perform_medias(plgsys, library_paths, total_path, Replace::Libraries(&titles)).await
+    }
+
+    /// Rewrite the cover urls of saved medias with the new public url.
+    pub async fn update_public_url(&self, public_url: &str) -> DbResult<u64> {
+        let _guard = self.scan_lock.lock().await;
+        let r = sqlx::query("UPDATE medias SET cover_url = ? || '/api/media_cover/' || id WHERE cover_url IS NOT NULL")
+            .bind(public_url)
+            .execute(&self.db)
+            .await?;
+        Ok(r.rows_affected())
+    }
+
+    /// Build the query of media by id which is not in the hidden libraries.
+    fn get_media_by_id_query<'a>(&self, main: &str, id: i64, hidden_libraries: &[String]) -> QueryBuilder<'a, Sqlite> {
+        let mut builder = QueryBuilder::new(main);
+        builder.push(" WHERE id = ").push_bind(id);
+        if !hidden_libraries.is_empty() {
+            builder.push(" AND");
+            push_hidden_libraries(&mut builder, "library", hidden_libraries);
+        }
+        builder.push(" LIMIT 1");
+        builder
     }
 
-    pub async fn get_media_file_by_id(&self, id: i64) -> Option<PathBuf> {
-        sqlx::query("SELECT path FROM medias WHERE id=? LIMIT 1")
-            .bind(id)
+    pub async fn get_media_file_by_id(&self, id: i64, hidden_libraries: &[String]) -> DbResult<Option<PathBuf>> {
+        let row = self
+            .get_media_by_id_query("SELECT path FROM medias", id, hidden_libraries)
+            .build()
             .fetch_optional(&self.db)
-            .await
-            .expect("Get media file by id failed!")
-            .map(|row| {
-                let str: String = row.get("path");
-                Path::new(&str).to_path_buf()
-            })
+            .await?;
+        match row {
+            Some(row) => Ok(Some(Path::new(&row.try_get::<String, _>("path")?).to_path_buf())),
+            None => Ok(None),
+        }
     }
 
-    pub async fn get_media_cover_file_by_id(&self, id: i64) -> Option<PathBuf> {
-        sqlx::query("SELECT cover_path FROM medias WHERE id=? LIMIT 1")
-            .bind(id)
+    /// Return `None` if the media is not exists or has no cover.
+    pub async fn get_media_cover_file_by_id(&self, id: i64, hidden_libraries: &[String]) -> DbResult<Option<PathBuf>> {
+        let row = self
+            .get_media_by_id_query("SELECT cover_path FROM medias", id, hidden_libraries)
+            .build()
             .fetch_optional(&self.db)
-            .await
-            .expect("Get media cover file by id failed!")
-            .map(|row| {
-                let str: String = row.get("cover_path");
-                Path::new(&str).to_path_buf()
-            })
+            .await?;
+        match row {
+            Some(row) => Ok(row.try_get::<Option<String>, _>("cover_path")?.map(|str| Path::new(&str).to_path_buf())),
+            None => Ok(None),
+        }
     }
 
-    pub async fn get_media_info_by_id(&self, id: i64) -> Option<MediaInfo> {
-        if let Some(row) = sqlx::query("SELECT * FROM medias WHERE id = ? LIMIT 1")
-            .bind(id)
+    pub async fn get_media_info_by_id(&self, id: i64, hidden_libraries: &[String]) -> DbResult<Option<MediaInfo>> {
+        if let Some(row) = self
+            .get_media_by_id_query("SELECT * FROM medias", id, hidden_libraries)
+            .build()
             .fetch_optional(&self.db)
-            .await
-            .expect("Get media by id failed!")
+            .await?
         {
-            Some(self.from_row(row).await)
+            Ok(Some(self.media_from_row(row).await?))
         } else {
-            None
+            Ok(None)
         }
     }
 
-    pub fn get_sources_core_query<'a>(&self, main: &str, source: Source<'a>, col: &str, to_search: Option<&str>) -> QueryBuilder<'a, Sqlite> {
+    pub async fn get_media_info_by_path(&self, path: &str, hidden_libraries: &[String]) -> DbResult<Option<MediaInfo>> {
+        let mut builder = QueryBuilder::new("SELECT * FROM medias WHERE path = ");
+        builder.push_bind(path.to_owned());
+        if !hidden_libraries.is_empty() {
+            builder.push(" AND");
+            push_hidden_libraries(&mut builder, "library", hidden_libraries);
+        }
+        builder.push(" LIMIT 1");
+        match builder.build().fetch_optional(&self.db).await? {
+            Some(row) => Ok(Some(self.media_from_row(row).await?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_sources_core_query<'a>(&self, main: &str, source: Source<'a>, col: &str, to_search: Option<&str>, hidden_libraries: &[String]) -> QueryBuilder<'a, Sqlite> {
         let mut builder = QueryBuilder::new(main);
+        push_sources_conditions(&mut builder, source, hidden_libraries);
         match source {
             Source::Any | Source::Library(_) => {
                 builder.push(" GROUP BY library");
@@ -260,7 +386,7 @@ impl LibrarySystem {
                 builder.push(" GROUP BY genre");
             }
             Source::Year(_) => {
-                builder.push(" WHERE year > 0 GROUP BY year");
+                builder.push(" GROUP BY year");
             }
         };
         if let Some(v) = to_search {
@@ -269,7 +395,7 @@ impl LibrarySystem {
         builder
     }
 
-    pub async fn get_sources<'a>(&self, source: Source<'a>, to_search: Option<&str>, index: usize, limit: usize) -> Vec<SourceInfo> {
+    pub async fn get_sources<'a>(&self, source: Source<'a>, to_search: Option<&str>, index: usize, limit: usize, hidden_libraries: &[String]) -> DbResult<Vec<SourceInfo>> {
         let col = match source {
             Source::Category(_) => "category_title",
             Source::Album(_) => "album",
@@ -283,7 +409,7 @@ impl LibrarySystem {
             _ => "medias",
         };
         let main = format!("SELECT COUNT(1) AS count, {col} AS label FROM {table}");
-        let mut builder = self.get_sources_core_query(&main, source, col, to_search);
+        let mut builder = self.get_sources_core_query(&main, source, col, to_search, hidden_libraries);
         let rows = builder
             .push(" LIMIT ")
             .push_bind(limit as i64)
@@ -291,19 +417,18 @@ impl LibrarySystem {
             .push_bind((index * limit) as i64)
             .build()
             .fetch_all(&self.db)
-            .await
-            .expect("Get all source failed!");
+            .await?;
         let mut result = Vec::with_capacity(rows.len());
         for row in rows {
             result.push(SourceInfo {
-                title: row.get("label"),
-                total_media: row.get("count"),
+                title: row.try_get("label")?,
+                total_media: row.try_get("count")?,
             })
         }
-        result
+        Ok(result)
     }
 
-    pub async fn get_total_source<'a>(&self, source: Source<'a>) -> usize {
+    pub async fn get_total_source<'a>(&self, source: Source<'a>, hidden_libraries: &[String]) -> DbResult<usize> {
         let col = match source {
             Source::Category(_) => "category_title",
             Source::Album(_) => "album",
@@ -316,59 +441,48 @@ impl LibrarySystem {
             Source::Category(_) => "media_categories",
             _ => "medias",
         };
-        let wq = match source {
-            Source::Year(_) => "WHERE year > 0",
-            _ => "",
-        };
-        let main = format!("SELECT COUNT(DISTINCT {col}) AS count FROM {table} {wq}");
-        QueryBuilder::new(&main)
-            .build()
-            .fetch_optional(&self.db)
-            .await
-            .expect("Get total media failed!")
-            .map(|row| row.get::<u32, _>("count") as usize)
-            .unwrap_or(0)
+        let main = format!("SELECT COUNT(DISTINCT {col}) AS count FROM {table}");
+        let mut builder = QueryBuilder::new(&main);
+        push_sources_conditions(&mut builder, source, hidden_libraries);
+        let row = builder.build().fetch_one(&self.db).await?;
+        Ok(row.try_get::<u32, _>("count")? as usize)
     }
 
-    pub fn get_medias_core_query<'a>(&self, main: &str, source: Source<'a>, to_search: Option<&str>) -> QueryBuilder<'a, Sqlite> {
+    pub fn get_medias_core_query<'a>(&self, main: &str, source: Source<'a>, to_search: Option<&str>, hidden_libraries: &[String]) -> QueryBuilder<'a, Sqlite> {
         let mut builder = QueryBuilder::new(main);
+        let mut has_condition = false;
 
-        let exists_source = match source {
-            Source::Any => false,
-            _ => true,
-        };
-        let is_search = to_search.is_some() && !to_search.as_ref().unwrap().trim().is_empty();
-        if exists_source || is_search {
-            builder.push(" WHERE");
-        }
         match source {
             Source::Any => (),
             Source::Library(v) => {
+                push_condition(&mut builder, &mut has_condition);
                 builder.push(" library = ").push_bind(v);
             }
             Source::Category(v) => {
+                push_condition(&mut builder, &mut has_condition);
                 builder.push(" category_title = ").push_bind(v);
             }
             Source::Album(v) => {
+                push_condition(&mut builder, &mut has_condition);
                 builder.push(" album = ").push_bind(v);
             }
             Source::Artist(v) => {
+                push_condition(&mut builder, &mut has_condition);
                 builder.push(" artist = ").push_bind(v);
             }
             Source::Genre(v) => {
+                push_condition(&mut builder, &mut has_condition);
                 builder.push(" genre = ").push_bind(v);
             }
             Source::Year(v) => {
                 if v > 0 {
+                    push_condition(&mut builder, &mut has_condition);
                     builder.push(" year = ").push_bind(v);
                 }
             }
         };
-        if is_search {
-            let search = to_search.unwrap();
-            if exists_source {
-                builder.push(" AND");
-            }
+        if let Some(search) = to_search.filter(|s| !s.trim().is_empty()) {
+            push_condition(&mut builder, &mut has_condition);
             let v = format!("%{search}%");
             builder
                 .push(" (title LIKE ")
@@ -381,15 +495,19 @@ impl LibrarySystem {
                 .push_bind(v.clone())
                 .push(")");
         }
+        if !hidden_libraries.is_empty() {
+            push_condition(&mut builder, &mut has_condition);
+            push_hidden_libraries(&mut builder, "library", hidden_libraries);
+        }
         builder
     }
 
-    pub async fn get_medias<'a>(&self, source: Source<'a>, to_search: Option<&str>, index: usize, limit: usize) -> Vec<MediaInfo> {
+    pub async fn get_medias<'a>(&self, source: Source<'a>, to_search: Option<&str>, index: usize, limit: usize, hidden_libraries: &[String]) -> DbResult<Vec<MediaInfo>> {
         let main = match source {
             Source::Category(_) => "SELECT * FROM medias INNER JOIN media_categories ON media_categories.media_id = id",
             _ => "SELECT * FROM medias",
         };
-        let mut builder = self.get_medias_core_query(main, source, to_search);
+        let mut builder = self.get_medias_core_query(main, source, to_search, hidden_libraries);
         let rows = builder
             .push(" LIMIT ")
             .push_bind(limit as i64)
@@ -397,79 +515,112 @@ impl LibrarySystem {
             .push_bind((index * limit) as i64)
             .build()
             .fetch_all(&self.db)
-            .await
-            .expect("Get all medias failed!");
+            .await?;
         let mut result = Vec::with_capacity(rows.len());
         for row in rows {
-            result.push(self.from_row(row).await);
+            result.push(self.media_from_row(row).await?);
         }
-        result
+        Ok(result)
     }
 
-    pub async fn get_total_media<'a>(&self, source: Source<'a>, to_search: Option<&str>) -> usize {
+    pub async fn get_total_media<'a>(&self, source: Source<'a>, to_search: Option<&str>, hidden_libraries: &[String]) -> DbResult<usize> {
         let main = match source {
             Source::Category(_) => "SELECT COUNT(1) AS count FROM medias INNER JOIN media_categories ON media_categories.media_id = id",
             _ => "SELECT COUNT(1) AS count FROM medias",
         };
-        self.get_medias_core_query(main, source, to_search)
-            .build()
-            .fetch_optional(&self.db)
-            .await
-            .expect("Get total media failed!")
-            .map(|row| row.get::<u32, _>("count") as usize)
-            .unwrap_or(0)
+        let row = self.get_medias_core_query(main, source, to_search, hidden_libraries).build().fetch_one(&self.db).await?;
+        Ok(row.try_get::<u32, _>("count")? as usize)
     }
 
-    pub async fn from_row(&self, row: SqliteRow) -> MediaInfo {
-        let id = row.get("id");
-        MediaInfo {
+    pub async fn media_from_row(&self, row: SqliteRow) -> DbResult<MediaInfo> {
+        let id = row.try_get("id")?;
+        Ok(MediaInfo {
             id,
-            title: row.get("title"),
-            album: row.get("album"),
-            artist: row.get("artist"),
-            genre: row.get("genre"),
-            year: row.get("year"),
-            library: row.get("library"),
-            path: Path::new(&row.get::<String, _>("path")).to_path_buf(),
+            title: row.try_get("title")?,
+            album: row.try_get("album")?,
+            artist: row.try_get("artist")?,
+            genre: row.try_get("genre")?,
+            year: row.try_get("year")?,
+            library: row.try_get("library")?,
+            path: Path::new(&row.try_get::<String, _>("path")?).to_path_buf(),
             cover_path: {
-                let str: Option<String> = row.get("cover_path");
+                let str: Option<String> = row.try_get("cover_path")?;
                 str.map(|p| Path::new(&p).to_path_buf())
             },
-            cover_url: row.get("cover_url"),
+            cover_url: row.try_get("cover_url")?,
             categories: sqlx::query("SELECT category_title FROM media_categories WHERE media_id=?")
                 .bind(id)
                 .fetch_all(&self.db)
-                .await
-                .expect("Fetch all categories failed!")
+                .await?
                 .into_iter()
-                .map(|row| row.get("category_title"))
-                .collect(),
-            sample_rate: row.get("sample_rate"),
-            bit_depth: row.get("bit_depth"),
-            audio_bitrate: row.get("audio_bitrate"),
-            overall_bitrate: row.get("overall_bitrate"),
-            channels: row.get("channels"),
-            duration_seconds: row.get("duration_seconds"),
-            file_name: row.get("file_name"),
-            file_type: row.get("file_type"),
+                .map(|row| row.try_get("category_title"))
+                .collect::<Result<_, _>>()?,
+            sample_rate: row.try_get("sample_rate")?,
+            bit_depth: row.try_get("bit_depth")?,
+            audio_bitrate: row.try_get("audio_bitrate")?,
+            overall_bitrate: row.try_get("overall_bitrate")?,
+            channels: row.try_get("channels")?,
+            duration_seconds: row.try_get("duration_seconds")?,
+            file_name: row.try_get("file_name")?,
+            file_type: row.try_get("file_type")?,
+        })
+    }
+}
+
+/// Push ` WHERE` before the first condition and ` AND` before the others.
+fn push_condition(builder: &mut QueryBuilder<'_, Sqlite>, has_condition: &mut bool) {
+    builder.push(if *has_condition { " AND" } else { " WHERE" });
+    *has_condition = true;
+}
+
+/// Conditions shared by the sources queries.
+fn push_sources_conditions(builder: &mut QueryBuilder<'_, Sqlite>, source: Source<'_>, hidden_libraries: &[String]) {
+    let mut has_condition = false;
+    if let Source::Year(_) = source {
+        push_condition(builder, &mut has_condition);
+        builder.push(" year > 0");
+    }
+    if !hidden_libraries.is_empty() {
+        push_condition(builder, &mut has_condition);
+        match source {
+            Source::Category(_) => {
+                builder.push(" media_id IN (SELECT id FROM medias WHERE");
+                push_hidden_libraries(builder, "library", hidden_libraries);
+                builder.push(")");
+            }
+            _ => push_hidden_libraries(builder, "library", hidden_libraries),
         }
     }
 }
 
+/// Match the medias of libraries.
+fn push_libraries(builder: &mut QueryBuilder<'_, Sqlite>, titles: &[String]) {
+    builder.push(" library IN (");
+    let mut separated = builder.separated(", ");
+    for title in titles {
+        separated.push_bind(title.clone());
+    }
+    separated.push_unseparated(")");
+}
+
+/// Exclude the medias of hidden libraries.
+fn push_hidden_libraries(builder: &mut QueryBuilder<'_, Sqlite>, col: &str, hidden_libraries: &[String]) {
+    builder.push(format!(" {col} NOT IN ("));
+    let mut separated = builder.separated(", ");
+    for library in hidden_libraries {
+        separated.push_bind(library.clone());
+    }
+    separated.push_unseparated(")");
+}
+
 fn get_image_path_media(path: &PathBuf) -> Option<PathBuf> {
     let file_name = myutil::get_file_name_without_ext(path);
     let parent = path.parent().unwrap();
     let png = Path::new(parent).join(format!("{}.png", file_name));
     let jpg = Path::new(parent).join(format!("{}.jpg", file_name));
-    let cover = if png.exists() {
-        png
-    } else if jpg.exists() {
-        jpg
-    } else {
-        jpg
-    };
+    let cover = if png.exists() { png } else { jpg };
     if cover.is_file() {
-        return Some(cover);
+        Some(cover)
     } else {
         None
     }
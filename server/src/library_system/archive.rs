@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use async_zip::{tokio::write::ZipFileWriter, Compression, ZipEntryBuilder};
+use futures_util::{AsyncWrite as FuturesAsyncWrite, AsyncWriteExt};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+};
+use tracing::warn;
+
+use super::model::MediaInfo;
+
+const PLAYLIST_FILE_NAME: &str = "playlist.m3u8";
+/// Lists the files skipped or truncated, it's only written if there are some.
+const ERRORS_FILE_NAME: &str = "ERRORS.txt";
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Write the medias, the first found cover and a M3U8 playlist into a ZIP archive.
+/// Entries are stored without compression and copied file by file,
+/// so the archive is never buffered in memory.
+/// As the response has been started, the files failed to read are skipped or truncated rather than
+/// aborting the archive, and they are listed in the `ERRORS.txt` entry so the client can tell.
+pub async fn write_medias_archive<W>(medias: &[MediaInfo], writer: W) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let mut names = HashSet::with_capacity(medias.len());
+    let mut playlist = String::from("#EXTM3U\n");
+    let mut errors = vec![];
+
+    for media in medias {
+        let mut file = match fs::File::open(&media.path).await {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("Open media `{:?}` failed, skip it in the archive: {err}", media.path);
+                errors.push(format!("{}: skipped, {err}", file_name(media)));
+                continue;
+            }
+        };
+        let name = unique_entry_name(&mut names, media);
+        let mut entry = zip.write_entry_stream(ZipEntryBuilder::new(name.clone().into(), Compression::Stored)).await?;
+        if let Some(err) = copy_entry(&mut file, &mut entry).await? {
+            warn!("Read media `{:?}` failed, it's truncated in the archive: {err}", media.path);
+            errors.push(format!("{name}: truncated, {err}"));
+        }
+        entry.close().await?;
+
+        playlist.push_str(&format!("#EXTINF:{},{} - {}\n{name}\n", media.duration_seconds, media.artist, media.title));
+    }
+
+    let cover = medias.iter().filter_map(|media| media.cover_path.as_ref()).find(|path| path.is_file());
+    let cover = match cover {
+        Some(cover) => match fs::File::open(cover).await {
+            Ok(file) => Some((cover, file)),
+            Err(err) => {
+                warn!("Open cover `{cover:?}` failed, skip it in the archive: {err}");
+                errors.push(format!("cover: skipped, {err}"));
+                None
+            }
+        },
+        None => None,
+    };
+    if let Some((cover, mut file)) = cover {
+        let ext = cover.extension().and_then(|ext| ext.to_str()).unwrap_or("jpg");
+        let name = format!("cover.{ext}");
+        let mut entry = zip.write_entry_stream(ZipEntryBuilder::new(name.clone().into(), Compression::Stored)).await?;
+        if let Some(err) = copy_entry(&mut file, &mut entry).await? {
+            warn!("Read cover `{cover:?}` failed, it's truncated in the archive: {err}");
+            errors.push(format!("{name}: truncated, {err}"));
+        }
+        entry.close().await?;
+    }
+
+    zip.write_entry_whole(ZipEntryBuilder::new(PLAYLIST_FILE_NAME.to_owned().into(), Compression::Stored), playlist.as_bytes())
+        .await?;
+    if !errors.is_empty() {
+        let content = format!("Some files are skipped or truncated in this archive:\n{}\n", errors.join("\n"));
+        zip.write_entry_whole(ZipEntryBuilder::new(ERRORS_FILE_NAME.to_owned().into(), Compression::Stored), content.as_bytes())
+            .await?;
+    }
+    let mut writer = zip.close().await?;
+    writer.close().await?;
+    Ok(())
+}
+
+/// Copy the file into the entry, the read error is returned to be reported in the archive,
+/// while the write error fails as the client is gone.
+async fn copy_entry<R, W>(file: &mut R, entry: &mut W) -> Result<Option<std::io::Error>>
+where
+    R: AsyncRead + Unpin,
+    W: FuturesAsyncWrite + Unpin,
+{
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    loop {
+        let len = match file.read(&mut buffer).await {
+            Ok(0) => return Ok(None),
+            Ok(len) => len,
+            Err(err) => return Ok(Some(err)),
+        };
+        entry.write_all(&buffer[..len]).await?;
+    }
+}
+
+fn file_name(media: &MediaInfo) -> String {
+    media
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("{}.{}", media.file_name, media.file_type))
+}
+
+/// Medias from different directories may have the same file name.
+fn unique_entry_name(names: &mut HashSet<String>, media: &MediaInfo) -> String {
+    let file_name = file_name(media);
+    let mut name = file_name.clone();
+    let mut count = 1;
+    while names.contains(&name) {
+        name = format!("({count}) {file_name}");
+        count += 1;
+    }
+    names.insert(name.clone());
+    name
+}
@@ -13,6 +13,7 @@ use lofty::{
 use serde::{Deserialize, Serialize};
 use tokio::{fs, io::AsyncWriteExt};
 use tracing::{info, warn};
+use utoipa::ToSchema;
 use walkdir::WalkDir;
 
 #[derive(Debug, PartialEq, Serialize, Deserialize, Eq, Hash, Clone)]
@@ -21,7 +22,7 @@ pub struct LibraryInfo {
     pub title: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 pub struct SourceInfo {
     pub title: String,
     pub total_media: u32,
@@ -114,7 +115,7 @@ pub struct MediaInfo {
 impl MediaInfo {
     pub fn from_meta(meta: MediaMetaInfo, config: Arc<Config>, library: &LibraryInfo, id: i64, path: PathBuf) -> Self {
         let public_url = config.public_url.as_str();
-        let categories = Self::get_categories_from_directory(&path, &library);
+        let categories = Self::get_categories_from_directory(&path, library);
         Self {
             id,
             path,
@@ -144,13 +145,13 @@ impl MediaInfo {
         }
     }
 
-    fn get_categories_from_directory(path: &PathBuf, lib: &LibraryInfo) -> Vec<String> {
+    fn get_categories_from_directory(path: &Path, lib: &LibraryInfo) -> Vec<String> {
         let mut categories = Vec::new();
         if let Some(parent) = path.parent() {
             if parent != lib.path {
                 if let Some(name) = parent.file_name() {
                     categories.push(name.to_str().unwrap().to_string());
-                    let mut grandparent = Self::get_categories_from_directory(&parent.to_path_buf(), &lib);
+                    let mut grandparent = Self::get_categories_from_directory(parent, lib);
                     categories.append(&mut grandparent);
                 }
             }
@@ -273,6 +274,7 @@ impl MediaMetaInfo {
                                     let mut fs = fs::OpenOptions::new()
                                         .write(true)
                                         .create(true)
+                                        .truncate(true)
                                         .open(&save_path)
                                         .await
                                         .with_context(|| "Open to write the cover file failed!")?;
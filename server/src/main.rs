@@ -1,19 +1,12 @@
-use std::sync::Arc;
-
 use clap::Parser;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 
-use crate::meta::{Commands, Meta};
-
-mod config;
-mod db;
-mod library_system;
-mod meta;
-mod myutil;
-mod plugin_system;
-mod server;
-mod user_system;
+use diosic::{
+    cli, config, db, library_system,
+    meta::{Commands, Meta},
+    plugin_system, server, share_system, user_system,
+};
 
 #[tokio::main]
 async fn main() {
@@ -23,41 +16,86 @@ async fn main() {
     {
         std::env::set_var("RUST_LOG", "actix_web=debug");
     }
+    // Logs go to stderr so the output of commands like `config check` stays clean.
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::INFO)
+        .with_writer(std::io::stderr)
         .finish();
     tracing::subscriber::set_global_default(subscriber).unwrap();
     tracing::info!("Starting tracing!");
 
+    let config = match config::Config::load(&meta).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Load config failed! {err:#}");
+            std::process::exit(1);
+        }
+    };
+
     match meta.command {
         Commands::Serve => {
-            let config = Arc::new(if let Some(config_path) = meta.config {
-                config::Config::load_from_path(&config_path)
-                    .await
-                    .expect("Load config from path failed!")
-            } else {
-                config::Config::load_from_meta(meta)
-                    .await
-                    .expect("Load config from meta failed!")
-            });
-            let db = db::init(config.clone())
+            config.clears().await.expect("Prepare the config directories failed!");
+            let config = config::SharedConfig::new(config);
+            let db = db::init(config.get())
                 .await
                 .expect("Initialize database failed!");
             let user_system = user_system::UserSystem::new(db.clone())
                 .await
                 .expect("Initialize user system failed!");
-            let plugin_system = plugin_system::PluginSystem::new(config.clone()).await;
+            if let Some(role) = config.get().proxy_auth.as_ref().and_then(|proxy| proxy.create_role.as_deref()) {
+                let exists = user_system.exists_role(role).await.expect("Check the role of proxy users failed!");
+                if !exists {
+                    panic!("The `proxy_auth.create_role` `{role}` is not exists!");
+                }
+            }
+            let share_system = share_system::ShareSystem::new(db.clone())
+                .await
+                .expect("Initialize share system failed!");
+            let oidc_client = user_system::oidc::OidcClient::new(&config.get())
+                .expect("Initialize single sign-on failed!");
+            let plugin_system = plugin_system::PluginSystem::new(db.clone(), config.clone())
+                .await
+                .expect("Initialize plugin system failed!");
             plugin_system.reload().await;
             let library_system =
-                library_system::LibrarySystem::new(db.clone(), config.clone()).await;
-            library_system.reload(&plugin_system).await;
+                library_system::LibrarySystem::new(db.clone(), config.clone())
+                    .await
+                    .expect("Initialize library system failed!");
+            db::stamp_schema_version(&db).await.expect("Stamp the schema version failed!");
+            // Serve while the first scan runs, `/readyz` reports it's not ready until the medias are loaded.
+            tokio::spawn({
+                let library_system = library_system.clone();
+                let plugin_system = plugin_system.clone();
+                async move { library_system.reload(&plugin_system).await }
+            });
+            if let Some(path) = meta.config {
+                tokio::spawn(config::watch::watch(
+                    path,
+                    config.clone(),
+                    library_system.clone(),
+                    plugin_system.clone(),
+                ));
+            }
+            tokio::spawn(db::backup::schedule(db.clone(), config.clone()));
             let s = server::AppState {
                 user_system,
-                library_system,
+                share_system,
+                oidc_client,
+                library_system: library_system.clone(),
                 plugin_system,
                 config: config.clone(),
+                db: db.clone(),
             };
-            server::run(config, s).await.expect("Run server error!");
+            server::run(config.get(), s).await.expect("Run server error!");
+            library_system.shutdown().await;
+            db.close().await;
+            tracing::info!("Server stopped.");
+        }
+        command => {
+            if let Err(err) = cli::run(command, config).await {
+                tracing::error!("{err:#}");
+                std::process::exit(1);
+            }
         }
     }
 }
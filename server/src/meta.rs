@@ -1,29 +1,44 @@
 use std::path::PathBuf;
 
-use clap::{command, Parser, Subcommand};
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Meta {
-    // path of config file.
-    #[arg(short, long)]
+    /// Path of config file, the format is chosen by the extension: `toml`, `json`, `yaml` or `yml`.
+    /// The other options are ignored if it is settled, use the `DIOSIC_*` environment variables to override the file.
+    #[arg(short, long, env = "DIOSIC_CONFIG")]
     pub config: Option<PathBuf>,
 
-    #[arg(short, long)]
+    #[arg(short, long, env = "DIOSIC_DATA_PATH")]
     pub data_path: Option<PathBuf>,
 
+    /// Library as `library_title;library_dir_path`, can be repeated.
+    /// Read from `DIOSIC_LIBRARIES` separated by `,` if not settled.
     #[arg(short, long)]
     pub library: Option<Vec<String>>,
 
-    #[arg(long, default_value = "0.0.0.0")]
+    #[arg(long, default_value = "0.0.0.0", env = "DIOSIC_HOST")]
     pub host: String,
 
-    #[arg(short, long, default_value = "3177")]
+    #[arg(short, long, default_value = "3177", env = "DIOSIC_PORT")]
     pub port: u16,
 
-    #[arg(long, default_value = "")]
+    #[arg(long, default_value = "", env = "DIOSIC_PUBLIC_URL")]
     pub public_url: String,
 
+    /// Prefix of the routes when Diosic is mounted under a sub-path behind a proxy, like `/diosic`.
+    #[arg(long, default_value = "", env = "DIOSIC_BASE_PATH")]
+    pub base_path: String,
+
+    /// Serve the web UI from this directory in place of the embedded one, for the development of web UI.
+    #[arg(long, env = "DIOSIC_WEBPAGE_DIR")]
+    pub webpage_dir: Option<PathBuf>,
+
+    /// Seconds the in-flight requests are allowed to finish after `SIGTERM` or `Ctrl-C`.
+    #[arg(long, default_value = "30", env = "DIOSIC_SHUTDOWN_TIMEOUT")]
+    pub shutdown_timeout: u64,
+
     #[command(subcommand)]
     pub command: Commands
 }
@@ -31,4 +46,104 @@ pub struct Meta {
 #[derive(Subcommand)]
 pub enum Commands {
     Serve,
+    /// Inspect the config.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Scan all libraries into the database and print a report.
+    Scan,
+    /// Manage the users in the database.
+    User {
+        #[command(subcommand)]
+        command: UserCommands,
+    },
+    /// Inspect the libraries of config.
+    Library {
+        #[command(subcommand)]
+        command: LibraryCommands,
+    },
+    /// Inspect and debug the plugins.
+    Plugin {
+        #[command(subcommand)]
+        command: PluginCommands,
+    },
+    /// Export or import the users, roles, shares and api keys.
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Request `/healthz` of the server listening on the first address of config, exit with 1 if it is unhealthy.
+    Healthcheck,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Validate the config and print the resolved result.
+    Check,
+}
+
+#[derive(Subcommand)]
+pub enum UserCommands {
+    List,
+    Add {
+        username: String,
+        /// Defaults to the username.
+        #[arg(long)]
+        alias: Option<String>,
+        #[arg(long, default_value = "user")]
+        role: String,
+        /// Read from stdin if not settled.
+        #[arg(long)]
+        password: Option<String>,
+    },
+    Remove {
+        username: String,
+    },
+    ResetPassword {
+        username: String,
+        /// Read from stdin if not settled.
+        #[arg(long)]
+        password: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum LibraryCommands {
+    /// List the libraries with the count of files in them.
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum PluginCommands {
+    List,
+    /// Check every plugin can be loaded without running it.
+    Validate,
+    /// Run the plugin against a single media file, print the media info before and after.
+    Run {
+        name: String,
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DbCommands {
+    /// Write the tables into a JSON file.
+    Export {
+        path: PathBuf,
+    },
+    /// Replace the tables by a JSON file from `export`.
+    Import {
+        path: PathBuf,
+    },
+    /// Take a consistent copy of the database, it's safe while the server is running.
+    Backup {
+        /// Defaults to `backup.dir` of the config or `{data_path}/backups`.
+        #[arg(long)]
+        dir: Option<PathBuf>,
+    },
+    /// Replace the database by a backup, stop the server first.
+    Restore {
+        path: PathBuf,
+    },
 }
@@ -0,0 +1,84 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Metrics of the whole process, exposed at `/metrics` in the Prometheus text format.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// Labeled by `method`, `route` and `status`, the route is the pattern so the ids don't explode the series.
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    /// Media files being streamed now.
+    pub active_streams: IntGauge,
+    pub stream_bytes: IntCounter,
+    pub scan_duration: Histogram,
+    pub scan_files_parsed: IntCounter,
+    pub scan_files_failed: IntCounter,
+    /// Labeled by `plugin`.
+    pub plugin_invocations: IntCounterVec,
+    pub plugin_errors: IntCounterVec,
+    pub plugin_duration: HistogramVec,
+    /// Medias of each library, labeled by `library`.
+    pub library_medias: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("diosic".to_owned()), None).expect("Create the metrics registry failed!");
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(Opts::new("http_requests_total", "HTTP requests handled."), &["method", "route", "status"]).unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "Time to produce the HTTP response."),
+                &["method", "route"],
+            )
+            .unwrap(),
+            active_streams: IntGauge::new("active_streams", "Media files being streamed.").unwrap(),
+            stream_bytes: IntCounter::new("stream_bytes_total", "Bytes of media files served.").unwrap(),
+            scan_duration: Histogram::with_opts(
+                HistogramOpts::new("scan_duration_seconds", "Time to perform the scanned medias.").buckets(exponential_buckets(0.1, 4.0, 8).unwrap()),
+            )
+            .unwrap(),
+            scan_files_parsed: IntCounter::new("scan_files_parsed_total", "Files parsed into medias by scans.").unwrap(),
+            scan_files_failed: IntCounter::new("scan_files_failed_total", "Files failed to parse by scans.").unwrap(),
+            plugin_invocations: IntCounterVec::new(Opts::new("plugin_invocations_total", "Calls of plugins."), &["plugin"]).unwrap(),
+            plugin_errors: IntCounterVec::new(Opts::new("plugin_errors_total", "Failed calls of plugins."), &["plugin"]).unwrap(),
+            plugin_duration: HistogramVec::new(
+                HistogramOpts::new("plugin_duration_seconds", "Time spent in plugins.").buckets(exponential_buckets(0.0005, 4.0, 8).unwrap()),
+                &["plugin"],
+            )
+            .unwrap(),
+            library_medias: IntGaugeVec::new(Opts::new("library_medias", "Medias of the library."), &["library"]).unwrap(),
+            registry,
+        };
+        let collectors: [Box<dyn prometheus::core::Collector>; 11] = [
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.active_streams.clone()),
+            Box::new(metrics.stream_bytes.clone()),
+            Box::new(metrics.scan_duration.clone()),
+            Box::new(metrics.scan_files_parsed.clone()),
+            Box::new(metrics.scan_files_failed.clone()),
+            Box::new(metrics.plugin_invocations.clone()),
+            Box::new(metrics.plugin_errors.clone()),
+            Box::new(metrics.plugin_duration.clone()),
+            Box::new(metrics.library_medias.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("Register the metrics failed!");
+        }
+        metrics
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Encode the metrics failed!");
+        String::from_utf8(buffer).expect("The metrics is not utf-8!")
+    }
+}
@@ -1,18 +1,39 @@
+pub mod manifest;
 pub mod model;
 
-use std::{collections::HashMap, fmt::Debug, path::PathBuf, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
-use crate::config::Config;
+use crate::{
+    config::{Config, SharedConfig},
+    db::{self, DbResult},
+};
+use anyhow::Result;
+use serde_json::Value;
+use sqlx::{Pool, Row, Sqlite};
 use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{error, info, warn};
 use walkdir::WalkDir;
 
-use self::model::PluginsContext;
+use self::{
+    manifest::{PluginManifest, PluginPermissions},
+    model::{Plugin, PluginsContext, MAIN_WASM_FILE_NAME},
+};
 
 #[derive(Clone)]
 pub struct PluginSystem {
-    config: Arc<Config>,
-    plugins: Arc<RwLock<HashMap<String, PathBuf>>>,
+    db: Pool<Sqlite>,
+    config: SharedConfig,
+    /// All scanned plugins by name, including the rejected ones.
+    plugins: Arc<RwLock<BTreeMap<String, Plugin>>>,
+    /// Set once the plugins have been scanned.
+    initialized: Arc<AtomicBool>,
 }
 
 impl Debug for PluginSystem {
@@ -24,16 +45,30 @@ impl Debug for PluginSystem {
 }
 
 impl PluginSystem {
-    pub async fn new(config: Arc<Config>) -> Self {
-        PluginSystem {
+    pub async fn new(db: Pool<Sqlite>, config: SharedConfig) -> Result<Self> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS plugins(
+                name varchar(128) PRIMARY KEY,
+                enabled BOOLEAN NOT NULL,
+                config TEXT NOT NULL,
+                granted_permissions TEXT NOT NULL DEFAULT '{}'
+            );",
+        )
+        .execute(&db)
+        .await?;
+        db::add_column_if_missing(&db, "plugins", "granted_permissions", "TEXT NOT NULL DEFAULT '{}'").await?;
+        Ok(PluginSystem {
+            db,
             config,
-            plugins: Arc::new(RwLock::new(HashMap::new())),
-        }
+            plugins: Arc::new(RwLock::new(BTreeMap::new())),
+            initialized: Arc::new(AtomicBool::new(false)),
+        })
     }
 
-    pub async fn scan(config: Arc<Config>) -> HashMap<String, PathBuf> {
+    /// Scan the plugins of `data_path`, the ones with an invalid `plugin.toml` are kept with the error.
+    async fn scan(config: Arc<Config>) -> BTreeMap<String, Plugin> {
         info!("Scanning all plugin..");
-        let mut plugins = HashMap::new();
+        let mut plugins = BTreeMap::new();
 
         let plugins_dir = config
             .data_path
@@ -57,14 +92,7 @@ impl PluginSystem {
                         if dir.is_dir() {
                             let filename = dir
                                 .file_name()
-                                .map(|s| {
-                                    if let Some(str) = s.to_str() {
-                                        Some(str)
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .unwrap_or(None);
+                                .and_then(|s| s.to_str());
                             if filename.is_some() && filename.unwrap().starts_with("diosic-plugin-")
                             {
                                 return Some(f);
@@ -75,28 +103,111 @@ impl PluginSystem {
                 });
             for dir in wasm_dirs {
                 let dir_path = dir.path();
-                let main_wasm_file = dir_path.join("main.wasm");
-                if !main_wasm_file.is_file() {
+                if !dir_path.join(MAIN_WASM_FILE_NAME).is_file() {
                     continue;
                 }
                 let name = dir.file_name().to_string_lossy().into_owned();
-                plugins.insert(name, main_wasm_file);
+                let manifest = PluginManifest::read(dir_path).await;
+                let error = match &manifest {
+                    Ok(manifest) => manifest.validate().err().map(|err| format!("{err:#}")),
+                    Err(err) => Some(format!("{err:#}")),
+                };
+                if let Some(err) = &error {
+                    warn!("Rejected the `{name}` plugin: {err}");
+                }
+                let plugin = Plugin {
+                    name: name.clone(),
+                    path: dir_path.to_path_buf(),
+                    manifest: manifest.ok(),
+                    error,
+                    enabled: true,
+                    config: Value::Object(Default::default()),
+                    granted: PluginPermissions::default(),
+                };
+                plugins.insert(name, plugin);
             }
         }
-        info!("Joined {} plugins.", plugins.len());
         plugins
     }
+
+    /// Apply the enabled state, config and granted permissions stored in database.
+    async fn load_states(&self, plugins: &mut BTreeMap<String, Plugin>) -> DbResult<()> {
+        let rows = sqlx::query("SELECT name, enabled, config, granted_permissions FROM plugins").fetch_all(&self.db).await?;
+        for row in rows {
+            let Some(plugin) = plugins.get_mut(row.get::<&str, _>("name")) else {
+                continue;
+            };
+            plugin.enabled = row.get("enabled");
+            plugin.config = serde_json::from_str(row.get("config"))?;
+            plugin.granted = serde_json::from_str(row.get("granted_permissions"))?;
+        }
+        Ok(())
+    }
+
     pub async fn reload(&self) {
-        *self.plugins.write().await = Self::scan(self.config.clone()).await;
+        let mut plugins = Self::scan(self.config.get()).await;
+        if let Err(err) = self.load_states(&mut plugins).await {
+            error!("Load the states of plugins failed, all plugins are disabled: {err}");
+            plugins.values_mut().for_each(|plugin| plugin.enabled = false);
+        }
+        for plugin in plugins.values_mut().filter(|plugin| plugin.error.is_none()) {
+            let Some(manifest) = &plugin.manifest else {
+                continue;
+            };
+            if let Err(errors) = manifest.validate_config(&plugin.config) {
+                warn!("Rejected the `{}` plugin, its config is invalid: {}", plugin.name, errors.join("; "));
+                plugin.error = Some(format!("The config is invalid: {}", errors.join("; ")));
+            } else if plugin.permissions() != manifest.permissions {
+                warn!("Some permissions requested by the `{}` plugin are not granted by the admin.", plugin.name);
+            }
+        }
+        let runnable = plugins.values().filter(|plugin| plugin.is_runnable()).count();
+        info!("Joined {runnable} plugins of {}.", plugins.len());
+        *self.plugins.write().await = plugins;
+        self.initialized.store(true, Ordering::Release);
+    }
+
+    /// Store the state of plugin and reload, it takes effect from the next scan of medias.
+    pub async fn update_plugin(&self, name: &str, enabled: bool, config: &Value, granted: &PluginPermissions) -> DbResult<()> {
+        sqlx::query(
+            "INSERT INTO plugins(name, enabled, config, granted_permissions) VALUES (?, ?, ?, ?)
+            ON CONFLICT(name) DO UPDATE SET enabled = excluded.enabled, config = excluded.config,
+            granted_permissions = excluded.granted_permissions",
+        )
+        .bind(name)
+        .bind(enabled)
+        .bind(serde_json::to_string(config)?)
+        .bind(serde_json::to_string(granted)?)
+        .execute(&self.db)
+        .await?;
+        self.reload().await;
+        Ok(())
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.load(Ordering::Acquire)
+    }
+
+    /// Count the runnable plugins.
+    pub async fn total_plugins(&self) -> usize {
+        self.plugins.read().await.values().filter(|plugin| plugin.is_runnable()).count()
     }
 
     pub async fn exists_plugins(&self) -> bool {
-        self.plugins.read().await.len() > 0
+        self.total_plugins().await > 0
+    }
+
+    pub async fn get_plugins(&self) -> Vec<Plugin> {
+        self.plugins.read().await.values().cloned().collect()
+    }
+
+    pub async fn get_plugin(&self, name: &str) -> Option<Plugin> {
+        self.plugins.read().await.get(name).cloned()
     }
 
     pub async fn init_plugins_context(&self) -> PluginsContext {
         info!("Initialing plugins context..");
-        let plugins = self.plugins.read().await;
+        let plugins = self.get_plugins().await;
         PluginsContext::new(&plugins).await
     }
 }
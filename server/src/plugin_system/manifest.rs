@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
+use utoipa::ToSchema;
+
+use super::model::{INIT_FUNCTION, PROCESS_MEDIA_INFO_JSON_FUNCTION};
+
+/// Version of the interface between the server and plugins, bumped on breaking changes.
+/// Since v2 the hooks get the input in a buffer of `alloc` and return their result buffer.
+pub const HOST_API_VERSION: u32 = 2;
+/// The oldest version still supported, the hooks of v1 get the input at the start of memory and reply by `callback`.
+pub const MIN_API_VERSION: u32 = 1;
+pub const MANIFEST_FILE_NAME: &str = "plugin.toml";
+/// Functions of plugin called by the server.
+pub const HOOKS: [&str; 2] = [INIT_FUNCTION, PROCESS_MEDIA_INFO_JSON_FUNCTION];
+
+/// The `plugin.toml` beside the `main.wasm` of plugin.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The host API the plugin is built for, from [`MIN_API_VERSION`] to [`HOST_API_VERSION`].
+    pub api_version: u32,
+    /// Hooks implemented by the plugin, only they are called.
+    pub hooks: Vec<String>,
+    /// JSON schema of the config, the plugin takes any config without it.
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    pub config_schema: Option<Value>,
+    #[serde(default)]
+    pub permissions: PluginPermissions,
+}
+
+/// Resources of the host, the manifest requests them and the admin grants them.
+/// The plugin has none of them by default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PluginPermissions {
+    /// Environment variables passed from the server, the missing ones are skipped.
+    #[serde(default)]
+    pub env: Vec<String>,
+    /// Directories preopened at the same path inside the plugin.
+    #[serde(default)]
+    pub dirs: Vec<PluginDir>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PluginDir {
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    /// The directory is read-only unless it's writable.
+    #[serde(default)]
+    pub writable: bool,
+}
+
+impl PluginPermissions {
+    /// The requested permissions which are also granted, a directory is writable only if both of them allow it.
+    pub fn granted_by(&self, granted: &PluginPermissions) -> PluginPermissions {
+        PluginPermissions {
+            env: self.env.iter().filter(|name| granted.env.contains(name)).cloned().collect(),
+            dirs: self
+                .dirs
+                .iter()
+                .filter_map(|dir| {
+                    let grant = granted.dirs.iter().find(|grant| grant.path == dir.path)?;
+                    Some(PluginDir {
+                        path: dir.path.clone(),
+                        writable: dir.writable && grant.writable,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Check the grant only contains the requested permissions.
+    pub fn check_grant(&self, granted: &PluginPermissions) -> Result<()> {
+        if let Some(name) = granted.env.iter().find(|name| !self.env.contains(name)) {
+            bail!("The environment variable `{name}` is not requested.");
+        }
+        for grant in &granted.dirs {
+            match self.dirs.iter().find(|dir| dir.path == grant.path) {
+                Some(dir) if grant.writable && !dir.writable => bail!("The directory `{}` is not requested to be writable.", grant.path.display()),
+                Some(_) => (),
+                None => bail!("The directory `{}` is not requested.", grant.path.display()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PluginManifest {
+    /// Read the manifest in the directory of plugin, it's not validated.
+    pub async fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE_NAME);
+        let content = fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Read `{}` failed!", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Parse `{}` failed!", path.display()))
+    }
+
+    /// Check the fields and the plugin is compatible with the server.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.trim().is_empty() {
+            bail!("The `name` can't be empty.");
+        }
+        if self.version.trim().is_empty() {
+            bail!("The `version` can't be empty.");
+        }
+        if !(MIN_API_VERSION..=HOST_API_VERSION).contains(&self.api_version) {
+            bail!(
+                "The plugin targets the host API v{}, but the server supports v{MIN_API_VERSION} to v{HOST_API_VERSION}.",
+                self.api_version
+            );
+        }
+        if self.hooks.is_empty() {
+            bail!("No hook is declared, expected some of {HOOKS:?}.");
+        }
+        if let Some(hook) = self.hooks.iter().find(|hook| !HOOKS.contains(&hook.as_str())) {
+            bail!("Unknown hook `{hook}`, expected some of {HOOKS:?}.");
+        }
+        if let Some(name) = self.permissions.env.iter().find(|name| name.is_empty() || name.contains('=')) {
+            bail!("Invalid environment variable `{name}` in `permissions.env`.");
+        }
+        if let Some(dir) = self.permissions.dirs.iter().find(|dir| !dir.path.is_absolute()) {
+            bail!("The directory `{}` in `permissions.dirs` must be absolute.", dir.path.display());
+        }
+        if let Some(schema) = &self.config_schema {
+            if let Err(err) = jsonschema::validator_for(schema) {
+                bail!("The `config_schema` is invalid: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the config against the `config_schema`, return the errors by the path of value.
+    pub fn validate_config(&self, config: &Value) -> Result<(), Vec<String>> {
+        let Some(schema) = &self.config_schema else {
+            return Ok(());
+        };
+        let validator = jsonschema::validator_for(schema).map_err(|err| vec![format!("The `config_schema` is invalid: {err}")])?;
+        let errors: Vec<_> = validator
+            .iter_errors(config)
+            .map(|err| match err.instance_path.as_str() {
+                "" => err.to_string(),
+                path => format!("`{path}`: {err}"),
+            })
+            .collect();
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.hooks.iter().any(|declared| declared == hook)
+    }
+}
@@ -1,47 +1,392 @@
-use std::{collections::HashMap, path::PathBuf, time};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{self, Duration},
+};
 
-use anyhow::Context;
-use tokio::{fs, sync::mpsc};
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::fs;
 use tracing::{error, info, warn};
-use wasmtime::{AsContextMut, Caller, Engine, Extern, Instance, Linker, Module, Store};
+use wasmtime::{Caller, Engine, Extern, ExternType, Instance, Linker, Module, ResourceLimiter, Store, Trap, TypedFunc};
 use wasmtime_wasi::{
     preview1::{self, WasiP1Ctx},
-    WasiCtxBuilder,
+    DirPerms, FilePerms, WasiCtxBuilder,
 };
 
-use crate::library_system::model::MediaInfo;
+use utoipa::ToSchema;
+
+use super::manifest::{PluginManifest, PluginPermissions};
+use crate::{library_system::model::MediaInfo, metrics::METRICS};
+
+pub const PROCESS_MEDIA_INFO_JSON_FUNCTION: &str = "process_media_info_json";
+/// Called once the plugin is instantiated, it takes the config in JSON.
+pub const INIT_FUNCTION: &str = "init";
+/// Exported by the plugins of API v2, `fn(len: u32) -> u32` gives a buffer for the input, `0` if it fails.
+pub const ALLOC_FUNCTION: &str = "alloc";
+/// Exported by the plugins of API v2, `fn(ptr: u32, len: u32)` frees the buffers of input and result.
+pub const DEALLOC_FUNCTION: &str = "dealloc";
+pub const MAIN_WASM_FILE_NAME: &str = "main.wasm";
+
+/// A `diosic-plugin-*` directory containing the `main.wasm`.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Plugin {
+    /// Name of the directory, it identifies the plugin.
+    pub name: String,
+    #[schema(value_type = String)]
+    pub path: PathBuf,
+    /// Missing if the `plugin.toml` can't be read.
+    pub manifest: Option<PluginManifest>,
+    /// Why the plugin is rejected, the rejected plugins are never run.
+    pub error: Option<String>,
+    /// The found plugins are enabled until they are disabled.
+    pub enabled: bool,
+    /// Passed to the `init` hook, it matches the `config_schema` of manifest.
+    #[schema(value_type = Object)]
+    pub config: Value,
+    /// Permissions approved by the admin, the plugin only gets the ones requested by the manifest as well.
+    pub granted: PluginPermissions,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PluginToUpdate {
+    pub enabled: Option<bool>,
+    #[schema(value_type = Option<Object>)]
+    pub config: Option<Value>,
+    /// Only admin can change it, and it must be a part of the permissions requested by the manifest.
+    pub granted: Option<PluginPermissions>,
+}
+
+impl Plugin {
+    pub fn wasm_path(&self) -> PathBuf {
+        self.path.join(MAIN_WASM_FILE_NAME)
+    }
+
+    pub fn is_runnable(&self) -> bool {
+        self.enabled && self.manifest.is_some() && self.error.is_none()
+    }
+
+    fn has_hook(&self, hook: &str) -> bool {
+        self.manifest.as_ref().is_some_and(|manifest| manifest.has_hook(hook))
+    }
+
+    /// The permissions both requested and granted.
+    pub fn permissions(&self) -> PluginPermissions {
+        match &self.manifest {
+            Some(manifest) => manifest.permissions.granted_by(&self.granted),
+            None => PluginPermissions::default(),
+        }
+    }
+}
+
+/// Parameters and results of the hooks by the API version, v1 hooks return nothing.
+fn hook_signature(api_version: u32) -> (&'static [&'static str], &'static [&'static str]) {
+    match api_version {
+        1 => (&["i32", "i32"], &[]),
+        _ => (&["i32", "i32"], &["i64"]),
+    }
+}
+
+fn check_function(module: &Module, name: &str, (params, results): (&[&str], &[&str])) -> anyhow::Result<()> {
+    match module.get_export(name) {
+        Some(ExternType::Func(ty)) => {
+            let actual_params: Vec<_> = ty.params().map(|ty| ty.to_string()).collect();
+            let actual_results: Vec<_> = ty.results().map(|ty| ty.to_string()).collect();
+            if actual_params != params || actual_results != results {
+                bail!(
+                    "`{name}` must be `({}) -> ({})`, but it's `({}) -> ({})`.",
+                    params.join(", "),
+                    results.join(", "),
+                    actual_params.join(", "),
+                    actual_results.join(", ")
+                );
+            }
+            Ok(())
+        }
+        Some(_) => bail!("`{name}` is not a function."),
+        None => bail!("Missing the export `{name}`."),
+    }
+}
+
+/// Check the module of plugin implements the hooks of its manifest without running it.
+pub async fn validate_plugin(plugin: &Plugin) -> anyhow::Result<()> {
+    if let Some(err) = &plugin.error {
+        bail!("{err}");
+    }
+    let Some(manifest) = &plugin.manifest else {
+        bail!("Missing the manifest.");
+    };
+    let path = plugin.wasm_path();
+    let bytes = fs::read(&path).await.with_context(|| format!("Read `{}` failed!", path.display()))?;
+    let module = Module::from_binary(&Engine::default(), &bytes)?;
+    for hook in &manifest.hooks {
+        check_function(&module, hook, hook_signature(manifest.api_version))?;
+    }
+    if manifest.api_version >= 2 {
+        check_function(&module, ALLOC_FUNCTION, (&["i32"], &["i32"]))?;
+        check_function(&module, DEALLOC_FUNCTION, (&["i32", "i32"], &[]))?;
+    }
+    if !matches!(module.get_export("memory"), Some(ExternType::Memory(_))) {
+        bail!("Missing the export `memory`.");
+    }
+    for import in module.imports() {
+        match (import.module(), import.name()) {
+            ("env", "callback") if manifest.api_version == 1 => (),
+            ("wasi_snapshot_preview1", _) => (),
+            (module, name) => bail!("Unknown import `{module}::{name}`."),
+        }
+    }
+    Ok(())
+}
+
+/// Memory a plugin can grow to.
+const MEMORY_LIMIT: usize = 256 << 20;
+/// Elements of tables a plugin can grow to.
+const TABLE_ELEMENTS_LIMIT: u32 = 100_000;
+/// Interval the epoch of engine is increased.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// Epochs a call of hook can run before it's interrupted, 10 seconds.
+const CALL_DEADLINE: u64 = 1000;
+/// The result of hook can't be larger than it.
+const RESULT_LIMIT: u32 = 16 << 20;
+
+/// The plugin grows over the limit of its store.
+#[derive(Debug, thiserror::Error)]
+#[error("Exceeded the limit of {0}.")]
+struct LimitExceeded(&'static str);
+
+/// The error returned by the hook of plugin itself.
+#[derive(Debug, thiserror::Error)]
+#[error("The plugin returned an error: {0}")]
+struct HookError(String);
+
+/// Data of the store of a plugin.
+struct PluginState {
+    wasi: WasiP1Ctx,
+    /// The result sent by `callback` in the last call of API v1.
+    output: Option<String>,
+}
+
+impl ResourceLimiter for PluginState {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> anyhow::Result<bool> {
+        if desired > MEMORY_LIMIT {
+            return Err(LimitExceeded("memory").into());
+        }
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: u32, desired: u32, _maximum: Option<u32>) -> anyhow::Result<bool> {
+        if desired > TABLE_ELEMENTS_LIMIT {
+            return Err(LimitExceeded("table elements").into());
+        }
+        Ok(true)
+    }
+
+    fn instances(&self) -> usize {
+        1
+    }
+}
+
+/// The WASI context of plugin, only the stdout, stderr and the granted permissions are given.
+fn build_wasi(plugin: &Plugin) -> anyhow::Result<WasiP1Ctx> {
+    let mut builder = WasiCtxBuilder::new();
+    builder.inherit_stdout().inherit_stderr();
+    let permissions = plugin.permissions();
+    for name in &permissions.env {
+        if let Ok(value) = std::env::var(name) {
+            builder.env(name, value);
+        }
+    }
+    for dir in &permissions.dirs {
+        let (dir_perms, file_perms) = match dir.writable {
+            true => (DirPerms::all(), FilePerms::all()),
+            false => (DirPerms::READ, FilePerms::READ),
+        };
+        builder
+            .preopened_dir(&dir.path, dir.path.to_string_lossy(), dir_perms, file_perms)
+            .with_context(|| format!("Preopen `{}` failed!", dir.path.display()))?;
+    }
+    Ok(builder.build_p1())
+}
+
+/// How the input is passed to the hooks and their result is got back.
+enum Abi {
+    /// API v1, the input is written at the start of memory and the result is sent by `callback`.
+    Legacy,
+    /// API v2, the input is written into a buffer of `alloc` and the hook returns the result buffer as `ptr << 32 | len`.
+    /// The result is `{"Ok": value}`, `{"Ok": null}` if nothing is changed, or `{"Err": "message"}`.
+    Buffer {
+        alloc: TypedFunc<u32, u32>,
+        dealloc: TypedFunc<(u32, u32), ()>,
+    },
+}
+
+/// A plugin instantiated in its own store.
+struct PluginInstance {
+    name: String,
+    store: Store<PluginState>,
+    instance: Instance,
+    abi: Abi,
+    /// Set once the plugin exceeds its limits, it's not called for the rest of the scan.
+    disabled: bool,
+}
+
+impl PluginInstance {
+    async fn new(engine: &Engine, linker: &Linker<PluginState>, plugin: &Plugin) -> anyhow::Result<Self> {
+        let manifest = plugin.manifest.as_ref().context("Missing the manifest.")?;
+        let path = plugin.wasm_path();
+        let bytes = fs::read(&path).await.with_context(|| format!("Read `{}` failed!", path.display()))?;
+        let module = Module::from_binary(engine, &bytes)?;
+        let state = PluginState {
+            wasi: build_wasi(plugin)?,
+            output: None,
+        };
+        let mut store = Store::new(engine, state);
+        store.limiter(|state| state);
+        store.set_epoch_deadline(CALL_DEADLINE);
+        let instance = linker.instantiate_async(&mut store, &module).await?;
+        let abi = match manifest.api_version {
+            1 => Abi::Legacy,
+            _ => Abi::Buffer {
+                alloc: instance
+                    .get_typed_func(&mut store, ALLOC_FUNCTION)
+                    .with_context(|| format!("Get `{ALLOC_FUNCTION}` failed!"))?,
+                dealloc: instance
+                    .get_typed_func(&mut store, DEALLOC_FUNCTION)
+                    .with_context(|| format!("Get `{DEALLOC_FUNCTION}` failed!"))?,
+            },
+        };
+        let mut plugin_instance = PluginInstance {
+            name: plugin.name.clone(),
+            store,
+            instance,
+            abi,
+            disabled: false,
+        };
+        if plugin.has_hook(INIT_FUNCTION) {
+            let config = serde_json::to_vec(&plugin.config)?;
+            plugin_instance
+                .call(INIT_FUNCTION, &config)
+                .await
+                .with_context(|| format!("Call `{INIT_FUNCTION}` failed!"))?;
+        }
+        Ok(plugin_instance)
+    }
+
+    /// Call the hook with the input and return its result, `None` if nothing is changed.
+    /// The deadline is reset for each call.
+    async fn call(&mut self, hook: &str, input: &[u8]) -> anyhow::Result<Option<Value>> {
+        let PluginInstance { store, instance, abi, .. } = self;
+        let memory = instance.get_memory(&mut *store, "memory").context("Missing the export `memory`.")?;
+        let len = u32::try_from(input.len()).context("The input is too large.")?;
+        store.set_epoch_deadline(CALL_DEADLINE);
+        match abi {
+            Abi::Legacy => {
+                let func = instance.get_typed_func::<(u32, u32), ()>(&mut *store, hook)?;
+                memory.write(&mut *store, 0, input).context("The input is larger than the memory.")?;
+                store.data_mut().output = None;
+                func.call_async(&mut *store, (0, len)).await?;
+                match store.data_mut().output.take() {
+                    Some(output) => Ok(Some(serde_json::from_str(&output)?)),
+                    None => Ok(None),
+                }
+            }
+            Abi::Buffer { alloc, dealloc } => {
+                let func = instance.get_typed_func::<(u32, u32), u64>(&mut *store, hook)?;
+                let ptr = alloc.call_async(&mut *store, len).await?;
+                if ptr == 0 {
+                    bail!("`{ALLOC_FUNCTION}` failed to allocate {len} bytes.");
+                }
+                let packed = match memory
+                    .write(&mut *store, ptr as usize, input)
+                    .with_context(|| format!("The buffer of `{ALLOC_FUNCTION}` is out of the memory."))
+                {
+                    Ok(()) => func.call_async(&mut *store, (ptr, len)).await,
+                    Err(err) => Err(err),
+                };
+                // The input is freed even if the hook fails, with a new deadline in case the hook used it up.
+                store.set_epoch_deadline(CALL_DEADLINE);
+                let freed = dealloc.call_async(&mut *store, (ptr, len)).await;
+                let packed = packed?;
+                freed?;
+                if packed == 0 {
+                    return Ok(None);
+                }
+                let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+                let output = match len {
+                    len if len > RESULT_LIMIT => Err(anyhow!("The result of {len} bytes is over the limit of {RESULT_LIMIT} bytes.")),
+                    _ => memory
+                        .data(&*store)
+                        .get(ptr as usize..)
+                        .and_then(|data| data.get(..len as usize))
+                        .map(<[u8]>::to_vec)
+                        .context("The result is out of the memory."),
+                };
+                // The result buffer belongs to the host now, so it's freed even if it's rejected.
+                dealloc.call_async(&mut *store, (ptr, len)).await?;
+                let output = output?;
+                let result: Result<Option<Value>, String> =
+                    serde_json::from_slice(&output).context("The result must be `{\"Ok\": value}` or `{\"Err\": \"message\"}`.")?;
+                result.map_err(|err| HookError(err).into())
+            }
+        }
+    }
+}
+
+/// Return `true` if the call is stopped by the deadline or limits of store.
+fn is_limit_exceeded(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Trap>() == Some(&Trap::Interrupt) || err.chain().any(|cause| cause.is::<LimitExceeded>())
+}
 
-const PROCESS_MEDIA_INFO_JSON_FUNCTION: &str = "process_media_info_json";
 pub struct PluginsContext {
-    store: Store<WasiP1Ctx>,
-    instances: Vec<(String, Instance)>,
-    process_media_info_rx: mpsc::Receiver<String>,
+    instances: Vec<PluginInstance>,
+    /// Stops increasing the epoch once the context is dropped.
+    ticker_stopped: Arc<AtomicBool>,
 }
 
 impl PluginsContext {
-    pub async fn new(plugins: &HashMap<String, PathBuf>) -> Self {
+    /// Instantiate the runnable plugins implementing the hooks of context, each one in its own store.
+    pub async fn new(plugins: &[Plugin]) -> Self {
+        let plugins: Vec<_> = plugins
+            .iter()
+            .filter(|plugin| plugin.is_runnable() && plugin.has_hook(PROCESS_MEDIA_INFO_JSON_FUNCTION))
+            .collect();
+        // No engine or ticker is needed if nothing runs, it's the usual case of scans.
+        if plugins.is_empty() {
+            return Self {
+                instances: vec![],
+                ticker_stopped: Arc::new(AtomicBool::new(true)),
+            };
+        }
         let mut wt_config = wasmtime::Config::new();
-        wt_config.async_support(true);
+        wt_config.async_support(true).epoch_interruption(true);
         let engine = Engine::new(&wt_config).expect("Initialize the wasmtime engine failed!");
-        let args = std::env::args().skip(1).collect::<Vec<_>>();
-        let mut linker: Linker<WasiP1Ctx> = Linker::new(&engine);
-        preview1::add_to_linker_async(&mut linker, |t| t)
+        let ticker_stopped = Arc::new(AtomicBool::new(false));
+        // The ticker is a thread, so the deadline works even if a plugin blocks the worker of runtime.
+        std::thread::spawn({
+            let engine = engine.clone();
+            let ticker_stopped = ticker_stopped.clone();
+            move || {
+                while !ticker_stopped.load(Ordering::Relaxed) {
+                    std::thread::sleep(EPOCH_TICK);
+                    engine.increment_epoch();
+                }
+            }
+        });
+
+        let mut linker: Linker<PluginState> = Linker::new(&engine);
+        preview1::add_to_linker_async(&mut linker, |state: &mut PluginState| &mut state.wasi)
             .with_context(|| "Wasi preview1 add to linker failed!")
             .unwrap();
-        let wasi_ctx: WasiP1Ctx = WasiCtxBuilder::new()
-            .inherit_stdio()
-            .inherit_env()
-            .args(&args)
-            .build_p1();
-
-        let (tx, process_media_info_rx) = mpsc::channel(plugins.len());
-        let tx = tx.clone();
         linker
             .func_wrap2_async(
                 "env",
                 "callback",
-                move |mut caller: Caller<'_, WasiP1Ctx>, ptr: u32, len: u32| {
-                    let tx = tx.clone();
+                move |mut caller: Caller<'_, PluginState>, ptr: u32, len: u32| {
                     Box::new(async move {
                         let mem = match caller.get_export("memory") {
                             Some(Extern::Memory(mem)) => mem,
@@ -49,63 +394,36 @@ impl PluginsContext {
                         };
                         let data = mem
                             .data(&caller)
-                            .get(ptr as u32 as usize..)
-                            .and_then(|arr| arr.get(..len as u32 as usize));
-                        match data {
-                            Some(data) => match std::str::from_utf8(data) {
-                                Ok(s) => tx
-                                    .send(s.to_owned())
-                                    .await
-                                    .expect("Send modified media info to rx failed!"),
-                                Err(_) => return error!("invalid utf-8"),
-                            },
-                            None => return error!("pointer/length out of bounds"),
-                        };
+                            .get(ptr as usize..)
+                            .and_then(|arr| arr.get(..len as usize));
+                        match data.map(std::str::from_utf8) {
+                            Some(Ok(s)) => caller.data_mut().output = Some(s.to_owned()),
+                            Some(Err(_)) => error!("invalid utf-8"),
+                            None => error!("pointer/length out of bounds"),
+                        }
                     })
                 },
             )
             .with_context(|| "wrap call back function failed!")
             .unwrap();
 
-        let mut store = Store::new(&engine, wasi_ctx);
         let mut instances = Vec::with_capacity(plugins.len());
-        for (name, file_path) in plugins {
+        for plugin in plugins {
+            let name = &plugin.name;
             let start = time::Instant::now();
-            let module_bytes = if let Ok(bytes) = fs::read(file_path).await {
-                bytes
-            } else {
-                warn!("Can't read the `{name}` plugin");
-                continue;
-            };
-            let module = if let Ok(module) = Module::from_binary(store.engine(), &module_bytes) {
-                module
-            } else {
-                warn!("Can't load the module from `{name}` plugin");
-                continue;
-            };
-
-            match linker.instantiate_pre(&module) {
-                Ok(pre) => match pre.instantiate_async(&mut store).await {
-                    Ok(v) => {
-                        instances.push((name.to_owned(), v));
-                        info!(
-                            "Initialized `{name}` in {:.2}s",
-                            start.elapsed().as_secs_f32()
-                        )
-                    }
-                    Err(err) => {
-                        warn!("Can't instantiate_pre the module from `{name}` plugin: {err}")
-                    }
-                },
-                Err(err) => warn!("Can't instantiated the module from `{name}` plugin: {err}"),
+            match PluginInstance::new(&engine, &linker, plugin).await {
+                Ok(instance) => {
+                    instances.push(instance);
+                    info!("Initialized `{name}` in {:.2}s", start.elapsed().as_secs_f32());
+                }
+                Err(err) => {
+                    METRICS.plugin_errors.with_label_values(&[name]).inc();
+                    warn!("Can't initialize the `{name}` plugin: {err:#}");
+                }
             }
         }
 
-        Self {
-            store,
-            instances,
-            process_media_info_rx,
-        }
+        Self { instances, ticker_stopped }
     }
 
     pub async fn process_media_info_json(&mut self, media: &mut MediaInfo) {
@@ -113,53 +431,185 @@ impl PluginsContext {
             return;
         }
         let json = serde_json::to_string(&media).unwrap();
-        let mut store = self.store.as_context_mut();
-
-        for (name, instance) in &self.instances {
-            let process_media_info_json: wasmtime::TypedFunc<(u32, u32), ()> = if let Ok(func) =
-                instance
-                    .get_typed_func::<(u32, u32), ()>(&mut store, PROCESS_MEDIA_INFO_JSON_FUNCTION)
-            {
-                func
-            } else {
-                warn!("Can't get the `{PROCESS_MEDIA_INFO_JSON_FUNCTION}` from `{name}` plugin",);
-                continue;
-            };
-
-            let memory = if let Some(memory) = instance.get_memory(&mut store, "memory") {
-                memory
-            } else {
-                warn!("Can't get the memory from `{name}` plugin");
-                continue;
-            };
-
-            let info_ptr = 0;
-            let info_len = json.as_bytes().len();
-            memory
-                .write(&mut store, info_ptr, json.as_bytes())
-                .expect("Write the media info to wasm memory failed!");
 
+        for plugin in self.instances.iter_mut().filter(|plugin| !plugin.disabled) {
+            let name = plugin.name.clone();
             info!(
                 "[{name}] Processing `{}`, path: `{:?}`",
                 media.title, media.path
             );
-            let result = process_media_info_json
-                .call_async(&mut store, (info_ptr as _, info_len as _))
-                .await;
+            let start = time::Instant::now();
+            let result = plugin.call(PROCESS_MEDIA_INFO_JSON_FUNCTION, json.as_bytes()).await;
+            METRICS.plugin_invocations.with_label_values(&[&name]).inc();
+            METRICS.plugin_duration.with_label_values(&[&name]).observe(start.elapsed().as_secs_f64());
 
             match result {
-                Ok(_) => {
-                    if let Ok(data) = self.process_media_info_rx.try_recv() {
-                        match serde_json::from_str(&data) {
-                            Ok(modified_info) => *media = modified_info,
-                            Err(err) => error!("Deserialize modified info failed: {err}"),
+                Ok(None) => (),
+                Ok(Some(modified_info)) => match serde_json::from_value::<MediaInfo>(modified_info) {
+                    // Plugins only change the metadata, the identity and files of media are kept.
+                    Ok(modified_info) => {
+                        *media = MediaInfo {
+                            id: media.id,
+                            path: media.path.clone(),
+                            cover_path: media.cover_path.clone(),
+                            ..modified_info
                         }
                     }
+                    Err(err) => {
+                        METRICS.plugin_errors.with_label_values(&[&name]).inc();
+                        error!("Deserialize modified info of `{name}` failed: {err}");
+                    }
+                },
+                Err(err) if is_limit_exceeded(&err) => {
+                    METRICS.plugin_errors.with_label_values(&[&name]).inc();
+                    plugin.disabled = true;
+                    error!("The `{name}` plugin is disabled for the rest of the scan: {err:#}");
                 }
                 Err(err) => {
-                    error!("Call `{PROCESS_MEDIA_INFO_JSON_FUNCTION}` error: {err}");
+                    METRICS.plugin_errors.with_label_values(&[&name]).inc();
+                    error!("Call `{PROCESS_MEDIA_INFO_JSON_FUNCTION}` of `{name}` error: {err:#}");
+                }
+            }
+        }
+    }
+}
+
+impl Drop for PluginsContext {
+    fn drop(&mut self) {
+        self.ticker_stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use serde_json::{json, Value};
+
+    use super::{is_limit_exceeded, Plugin, PluginInstance, PluginsContext, MAIN_WASM_FILE_NAME, PROCESS_MEDIA_INFO_JSON_FUNCTION};
+    use crate::plugin_system::manifest::{PluginManifest, PluginPermissions};
+
+    /// A plugin of API v2 whose `process_media_info_json` runs `body`, `dealloc` counts its calls in `freed`.
+    fn plugin(name: &str, body: &str, requested: &[&str], granted: &[&str]) -> Plugin {
+        let wat = format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "environ_sizes_get" (func $environ_sizes (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (global $freed (export "freed") (mut i32) (i32.const 0))
+                (data (i32.const 16) "{{\"Ok\":0}}")
+                (data (i32.const 32) "{{\"Err\":\"bad\"}}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "dealloc") (param i32 i32) (global.set $freed (i32.add (global.get $freed) (i32.const 1))))
+                (func (export "{PROCESS_MEDIA_INFO_JSON_FUNCTION}") (param i32 i32) (result i64) {body}))"#
+        );
+        let path = std::env::temp_dir().join(format!("diosic-plugin-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join(MAIN_WASM_FILE_NAME), wat::parse_str(wat).unwrap()).unwrap();
+        let env = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        Plugin {
+            name: name.to_owned(),
+            manifest: Some(PluginManifest {
+                name: name.to_owned(),
+                version: "1.0.0".to_owned(),
+                author: None,
+                description: None,
+                api_version: 2,
+                hooks: vec![PROCESS_MEDIA_INFO_JSON_FUNCTION.to_owned()],
+                config_schema: None,
+                permissions: PluginPermissions { env: env(requested), dirs: vec![] },
+            }),
+            path,
+            error: None,
+            enabled: true,
+            config: Value::Null,
+            granted: PluginPermissions { env: env(granted), dirs: vec![] },
+        }
+    }
+
+    async fn call(plugin: &Plugin) -> anyhow::Result<Option<Value>> {
+        let mut context = PluginsContext::new(std::slice::from_ref(plugin)).await;
+        let result = context.instances[0].call(PROCESS_MEDIA_INFO_JSON_FUNCTION, b"{}").await;
+        std::fs::remove_dir_all(&plugin.path).unwrap();
+        result
+    }
+
+    #[tokio::test]
+    async fn no_engine_without_runnable_plugins() {
+        let mut disabled = plugin("disabled", "(i64.const 0)", &[], &[]);
+        disabled.enabled = false;
+        let context = PluginsContext::new(&[disabled.clone()]).await;
+        std::fs::remove_dir_all(&disabled.path).unwrap();
+        assert!(context.instances.is_empty());
+        assert!(context.ticker_stopped.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn interrupt_endless_loop() {
+        let plugin = plugin("endless", "(loop (br 0)) (i64.const 0)", &[], &[]);
+        let mut context = PluginsContext::new(std::slice::from_ref(&plugin)).await;
+        std::fs::remove_dir_all(&plugin.path).unwrap();
+        // Tick faster than the ticker of context, so the deadline is reached soon.
+        let engine = context.instances[0].store.engine().clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = std::thread::spawn({
+            let done = done.clone();
+            move || {
+                while !done.load(Ordering::Relaxed) {
+                    engine.increment_epoch();
+                    std::thread::sleep(Duration::from_micros(10));
                 }
             }
+        });
+        let result = context.instances[0].call(PROCESS_MEDIA_INFO_JSON_FUNCTION, b"{}").await;
+        done.store(true, Ordering::Relaxed);
+        ticker.join().unwrap();
+        assert!(is_limit_exceeded(&result.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn limit_memory() {
+        // 512 MiB is over the limit.
+        let plugin = plugin("greedy", "(drop (memory.grow (i32.const 8192))) (i64.const 0)", &[], &[]);
+        assert!(is_limit_exceeded(&call(&plugin).await.unwrap_err()));
+    }
+
+    #[tokio::test]
+    async fn pass_only_granted_env() {
+        // Return `{"Ok": count}` of the environment variables it sees.
+        let body = r#"(drop (call $environ_sizes (i32.const 0) (i32.const 4)))
+            (i32.store8 (i32.const 22) (i32.add (i32.const 48) (i32.load (i32.const 0))))
+            (i64.const 0x10_0000_0008)"#;
+        let requested = plugin("env-requested", body, &["PATH"], &[]);
+        assert_eq!(call(&requested).await.unwrap(), Some(json!(0)));
+        let granted = plugin("env-granted", body, &["PATH"], &["PATH"]);
+        assert_eq!(call(&granted).await.unwrap(), Some(json!(1)));
+    }
+
+    #[tokio::test]
+    async fn free_buffers_of_every_result() {
+        // The packed `ptr << 32 | len` returned by hook, the result and the buffers freed by the host.
+        let cases = [
+            ("ok", "0x10_0000_0008", Ok(Some(json!(0))), 2),
+            ("err", "0x20_0000_000d", Err("The plugin returned an error: bad"), 2),
+            ("null", "0", Ok(None), 1),
+            ("oversize", "0x10_0100_0001", Err("The result of 16777217 bytes is over the limit of 16777216 bytes."), 2),
+            ("out-of-bounds", "0x1_0000_0000_0008", Err("The result is out of the memory."), 2),
+        ];
+        for (name, packed, expected, freed) in cases {
+            let plugin = plugin(&format!("result-{name}"), &format!("(i64.const {packed})"), &[], &[]);
+            let mut context = PluginsContext::new(std::slice::from_ref(&plugin)).await;
+            std::fs::remove_dir_all(&plugin.path).unwrap();
+            let result = context.instances[0].call(PROCESS_MEDIA_INFO_JSON_FUNCTION, b"{}").await;
+            assert_eq!(result.map_err(|err| err.to_string()), expected.map_err(str::to_owned), "{name}");
+            let PluginInstance { store, instance, .. } = &mut context.instances[0];
+            let global = instance.get_global(&mut *store, "freed").unwrap();
+            assert_eq!(global.get(&mut *store).i32(), Some(freed), "{name}");
         }
     }
 }
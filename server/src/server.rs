@@ -1,79 +1,222 @@
-use std::{path::PathBuf, sync::Arc};
+use std::sync::Arc;
 
 use actix_cors::Cors;
-use actix_files::NamedFile;
 use actix_web::{
+    http::Method,
     web::{self},
-    App, Error, HttpRequest, HttpServer,
+    App, Error, HttpServer,
 };
+use sqlx::{Pool, Sqlite};
 use tokio::time::Instant;
-use tracing::info;
+use tracing::{error, info};
 use tracing_actix_web::TracingLogger;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    config::Config, library_system::LibrarySystem, plugin_system::PluginSystem,
-    user_system::UserSystem,
+    config::{Config, CorsConfig, ListenAddr, SharedConfig}, library_system::LibrarySystem, plugin_system::PluginSystem,
+    share_system::ShareSystem,
+    user_system::{oidc::OidcClient, UserSystem},
 };
 
+use self::error::{APIError, APIErrorType};
+use self::openapi::ApiDoc;
+
 mod api;
 mod dto;
 mod error;
 mod from_requests;
+mod health;
+mod metrics;
+mod oidc;
+mod openapi;
+mod share;
+mod tls;
+mod webpage;
 
 pub struct AppState {
     pub user_system: UserSystem,
+    pub share_system: ShareSystem,
+    pub oidc_client: Option<OidcClient>,
     pub library_system: LibrarySystem,
     pub plugin_system: PluginSystem,
-    pub config: Arc<Config>,
+    pub config: SharedConfig,
+    pub db: Pool<Sqlite>,
 }
 
 pub async fn run(config: Arc<Config>, s: AppState) -> Result<(), std::io::Error> {
-    info!("Running server in {}:{}", &config.host, &config.port);
     let state = web::Data::new(s);
     let start = web::Data::new(Instant::now());
 
-    HttpServer::new(move || {
+    let openapi = ApiDoc::openapi();
+    let base_path = config.base_path.clone();
+    let cors_config = config.cors.clone();
+    let webpage = web::Data::new(webpage::Webpage::new(&config));
+
+    let library_system = state.library_system.clone();
+    let mut server = HttpServer::new(move || {
         App::new()
+            .wrap_fn(metrics::track)
             .wrap(TracingLogger::default())
-            .wrap(Cors::permissive())
+            .wrap(cors(cors_config.as_ref()))
             .app_data(state.clone())
             .app_data(start.clone())
-            .service(
-                web::scope("/api")
-                    .service(api::get_server_info)
-                    .service(api::setup)
-                    .service(api::get_media_file)
-                    .service(api::get_media_cover)
-                    .service(api::get_media_info)
-                    .service(api::get_medias)
-                    .service(api::get_sources)
-                    .service(api::get_medias)
-                    .service(api::create_user)
-                    .service(api::delete_user)
-                    .service(api::get_user)
-                    .service(api::get_users)
-                    .service(api::update_user)
-                    .service(api::login_user)
-                    .service(api::logout_user)
-                    .service(api::get_current_user)
-                    .service(api::reload_medias)
-                    .service(api::reload_plugins),
-            )
-            .service(
-                actix_files::Files::new("/", "./webpage")
-                    .show_files_listing()
-                    .index_file("index.html"),
-            )
-            .default_service(web::to(index))
+            .app_data(webpage.clone())
+            .app_data(web::QueryConfig::default().error_handler(|err, _| invalid_request(err)))
+            .app_data(web::JsonConfig::default().error_handler(|err, _| invalid_request(err)))
+            .app_data(web::PathConfig::default().error_handler(|err, _| invalid_request(err)))
+            .service(metrics::get_metrics)
+            .service(health::healthz)
+            .service(health::readyz)
+            .service(SwaggerUi::new(format!("{base_path}/api/docs/{{_:.*}}")).url(format!("{base_path}/api/openapi.json"), openapi.clone()))
+            .service(web::scope(&base_path).configure(routes).default_service(web::to(webpage::serve)))
     })
-    .bind((config.host.to_owned(), config.port))?
-    .run()
-    .await?;
+    .disable_signals()
+    .shutdown_timeout(config.shutdown_timeout);
+
+    let tls_config = config.tls.as_ref().map(tls::server_config).transpose().map_err(std::io::Error::other)?;
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    for addr in config.listen_addrs() {
+        server = match addr {
+            ListenAddr::Tcp(addr) => {
+                info!("Listening on {scheme}://{addr}{}", config.base_path);
+                match &tls_config {
+                    Some(tls_config) => server.bind_rustls(addr, tls_config.clone())?,
+                    None => server.bind(addr)?,
+                }
+            }
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                info!("Listening on unix:{}", path.display());
+                remove_stale_socket(&path)?;
+                server.bind_uds(path)?
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(path) => {
+                return Err(std::io::Error::other(format!("Unix socket `{}` is not supported on this platform.", path.display())));
+            }
+        };
+    }
+    let server = server.run();
+
+    let handle = server.handle();
+    let shutdown_timeout = config.shutdown_timeout;
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down, the in-flight requests have {shutdown_timeout}s to finish..");
+        tokio::join!(handle.stop(true), library_system.shutdown());
+    });
+    server.await
+}
+
+/// Routes under the `base_path`.
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api")
+            .service(api::get_server_info)
+            .service(api::setup)
+            .service(api::get_media_url)
+            .service(api::get_media_file)
+            .service(api::get_media_cover)
+            .service(api::get_media_info)
+            .service(api::get_medias_archive)
+            .service(api::get_medias)
+            .service(api::get_sources)
+            .service(api::create_user)
+            .service(api::delete_user)
+            .service(api::get_user)
+            .service(api::get_users)
+            .service(api::update_user)
+            .service(api::update_user_role)
+            .service(api::update_user_permissions)
+            .service(api::get_user_libraries)
+            .service(api::update_user_library)
+            .service(api::get_roles)
+            .service(api::save_role)
+            .service(api::delete_role)
+            .service(api::login_user)
+            .service(api::get_auth_logs)
+            .service(oidc::oidc_login)
+            .service(oidc::oidc_callback)
+            .service(api::logout_user)
+            .service(api::get_current_user)
+            .service(api::reload_medias)
+            .service(api::reload_plugins)
+            .service(api::get_plugins)
+            .service(api::get_plugin)
+            .service(api::update_plugin)
+            .service(api::create_share)
+            .service(api::get_shares)
+            .service(api::delete_share)
+            .service(api::create_api_key)
+            .service(api::get_api_keys)
+            .service(api::delete_api_key)
+            .service(api::create_backup)
+            .service(api::get_backups)
+            .service(api::download_backup),
+    )
+    .service(
+        web::scope("/share")
+            .service(share::get_share)
+            .service(share::get_share_media_file)
+            .service(share::get_share_media_cover)
+            .service(share::download_share_media_file),
+    );
+}
+
+/// Allow any origin if the config is not settled.
+fn cors(config: Option<&CorsConfig>) -> Cors {
+    let Some(config) = config else {
+        return Cors::permissive();
+    };
+    let mut cors = Cors::default().allow_any_header().expose_any_header().max_age(config.max_age);
+    for origin in &config.allowed_origins {
+        cors = match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        };
+    }
+    // The methods are checked by the validation of config.
+    cors = cors.allowed_methods(config.allowed_methods.iter().filter_map(|method| Method::from_bytes(method.as_bytes()).ok()));
+    if config.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
+
+/// Remove the socket file left by the last run, the other files are never touched.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
 
-    Ok(())
+/// Resolve when the process is asked to stop by `SIGTERM` or `Ctrl-C`.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                error!("Listen the `SIGTERM` failed: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => (),
+        _ = terminate => (),
+    }
 }
 
-async fn index(_req: HttpRequest) -> Result<NamedFile, Error> {
-    let path: PathBuf = "./webpage/index.html".parse().unwrap();
-    Ok(NamedFile::open(path)?)
+/// Report the request can't be extracted as the validation error.
+fn invalid_request(err: impl std::fmt::Display) -> Error {
+    APIError::with(APIErrorType::Validation).note(err.to_string()).into()
 }
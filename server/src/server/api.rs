@@ -1,16 +1,29 @@
 use actix_files::NamedFile;
 use actix_web::{
-    delete, get, post, put,
+    delete, get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    post, put,
     web::{self, Json},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
+use serde_json::json;
 use tokio::time::Instant;
+use tokio_util::io::ReaderStream;
 use tracing::{error, warn};
 
 use crate::{
-    library_system::model::SourceInfo,
+    db::backup::{self, BackupInfo},
+    library_system::{
+        archive,
+        model::{Source, SourceInfo},
+    },
+    plugin_system::model::{Plugin, PluginToUpdate},
     server::dto::{ListSlice, PubMediaInfo},
-    user_system::model::{UserInfo, UserToCreate},
+    share_system::model::{ShareInfo, ShareKind, ShareToCreate},
+    user_system::{
+        model::{ApiKeyCreated, ApiKeyInfo, ApiKeyToCreate, AuthLogInfo, Capability, LibraryAccess, LoginFailure, MediaSignature, RoleInfo, RoleToUpdate, SignedMedia, UserInfo, UserPermissionsToUpdate, UserToCreate, ADMIN_ROLE, GUEST_ROLE, USER_ROLE},
+        SIGNED_URL_EXPIRES_SECONDS,
+    },
 };
 
 use super::error::APIErrorType::*;
@@ -20,18 +33,66 @@ use super::{dto, AppState};
 
 type State = web::Data<AppState>;
 
+/// Resolve the owner of media request, the signature take precedence over the session token.
+async fn get_media_owner(state: &State, kind: SignedMedia, id: i64, permission: &UserPermission, signature: Option<web::Query<MediaSignature>>) -> Result<UserInfo, APIError> {
+    let owner = match signature {
+        Some(sig) => match state.user_system.verify_media_signature(kind, id, &sig).await? {
+            Some(owner) => owner,
+            None => return Err(APIError::with(Unauthorized).note("The signature is invalid or expired!")),
+        },
+        None if permission.exists_owner() => permission.get_owner()?,
+        None => return Err(APIError::with(Unauthorized).note("Please log in first!")),
+    };
+    if owner.can(Capability::Stream) {
+        Ok(owner)
+    } else {
+        Err(APIError::with(NoPermission).note("Missing the capability `Stream`."))
+    }
+}
+
+/// Only the admin can change the accounts of admin.
+fn check_admin_target(permission: &UserPermission, user: &UserInfo) -> Result<(), APIError> {
+    if user.is_admin && !permission.is_admin() {
+        return Err(APIError::with(NoPermission).note("Only admin can change the admin."));
+    }
+    Ok(())
+}
+
+/// Replace the `cover_url` of media with the signed url of owner.
+fn sign_media_cover(state: &State, mut media: PubMediaInfo, owner: &UserInfo) -> PubMediaInfo {
+    if let Some(url) = media.cover_url.take() {
+        let query = state.user_system.sign_media(SignedMedia::Cover, media.id, owner);
+        media.cover_url = Some(format!("{url}?{query}"));
+    }
+    media
+}
+
+#[utoipa::path(
+    tag = "server",
+    responses(
+        (status = 200, description = "Succeeded.", body = dto::ServerInfo)
+    )
+)]
 #[get("/server_info")]
-pub async fn get_server_info(state: State, start: web::Data<Instant>) -> Json<dto::ServerInfo> {
-    Json(dto::ServerInfo {
-        admin_required: !state.user_system.contains_users().await,
-        guest_enable: state.user_system.is_guest_enabled().await,
-        guest_password_required: state.user_system.is_guest_password_required().await,
+pub async fn get_server_info(state: State, start: web::Data<Instant>) -> Result<Json<dto::ServerInfo>, APIError> {
+    Ok(Json(dto::ServerInfo {
+        admin_required: !state.user_system.contains_users().await?,
+        guest_enable: state.user_system.is_guest_enabled().await?,
+        guest_password_required: state.user_system.is_guest_password_required().await?,
+        oidc_enable: state.oidc_client.is_some(),
         author: "Jinker",
         version: env!("CARGO_PKG_VERSION"),
         time_running: start.elapsed().as_secs(),
-    })
+    }))
 }
 
+#[utoipa::path(
+    tag = "server",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[put("/setup")]
 pub async fn setup(state: State, setup: Json<dto::ToSetup>, permission: UserPermission) -> Result<HttpResponse, APIError> {
     let setup = setup.0;
@@ -39,11 +100,11 @@ pub async fn setup(state: State, setup: Json<dto::ToSetup>, permission: UserPerm
         if setup.guest_enable {
             if let Some(pass) = &setup.guest_password {
                 if pass.len() < 8 {
-                    return Err(APIError::with(Unexpected).note("Password length must more than 8."));
+                    return Err(APIError::with(Validation).note("Password length must more than 8.").details(json!({ "field": "guest_password" })));
                 }
             }
-            if state.user_system.exists_user("guest").await {
-                let old_guest = state.user_system.get_user("guest").await;
+            if state.user_system.exists_user("guest").await? {
+                let old_guest = state.user_system.get_user("guest").await?;
                 state
                     .user_system
                     .update_user(
@@ -54,7 +115,7 @@ pub async fn setup(state: State, setup: Json<dto::ToSetup>, permission: UserPerm
                             password: setup.guest_password.clone().unwrap_or("".to_owned()),
                         },
                     )
-                    .await;
+                    .await?;
             } else {
                 state
                     .user_system
@@ -64,10 +125,10 @@ pub async fn setup(state: State, setup: Json<dto::ToSetup>, permission: UserPerm
                             username: "guest".to_owned(),
                             password: setup.guest_password.unwrap_or("".to_owned()),
                         },
-                        true,
+                        GUEST_ROLE,
                     )
                     .await
-                    .map_err(|err| APIError::with(Unexpected).note(format!("Create guest error: {}", err.to_string())))?;
+                    .map_err(|err| APIError::with(Unexpected).note(format!("Create guest error: {}", err)))?;
             }
         }
         state
@@ -78,14 +139,16 @@ pub async fn setup(state: State, setup: Json<dto::ToSetup>, permission: UserPerm
                     username: setup.username,
                     password: setup.password,
                 },
-                true,
+                ADMIN_ROLE,
             )
             .await
-            .map_err(|err| APIError::with(Unexpected).note(format!("Create user error: {}", err.to_string())))?;
+            .map_err(|err| APIError::with(Unexpected).note(format!("Create user error: {}", err)))?;
         Ok(HttpResponse::Ok().finish())
     };
-    if state.user_system.contains_users().await {
-        if permission.is_admin() {
+    if state.user_system.contains_users().await? {
+        if permission.is_by_api_key() {
+            Err(APIError::with(NoPermission).note("API key can't do it, please log in."))
+        } else if permission.is_admin() {
             match state.user_system.delete_user(&permission.get_owner().unwrap()).await {
                 Ok(_) => (),
                 Err(err) => error!("can't delete current admin: {}", err),
@@ -99,24 +162,62 @@ pub async fn setup(state: State, setup: Json<dto::ToSetup>, permission: UserPerm
     }
 }
 
-#[get("/media_file/{id}")]
-pub async fn get_media_file(state: State, info: web::Path<(i64,)>, permission: UserPermission) -> Result<NamedFile, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
+#[utoipa::path(
+    tag = "media",
+    responses(
+        (status = 200, description = "Succeeded.", body = dto::SignedMediaUrl),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/media_url/{id}")]
+pub async fn get_media_url(state: State, info: web::Path<(i64,)>, permission: Require<guard::Stream>) -> Result<Json<dto::SignedMediaUrl>, APIError> {
+    let owner = permission.get_owner()?;
+    match state.library_system.get_media_info_by_id(info.0, &owner.hidden_libraries).await? {
+        Some(media) => {
+            let config = state.config.get();
+            let public_url = config.public_url.as_str();
+            let query = state.user_system.sign_media(SignedMedia::File, media.id, &owner);
+            let media = sign_media_cover(&state, media.into(), &owner);
+            Ok(Json(dto::SignedMediaUrl {
+                file_url: format!("{public_url}/api/media_file/{}?{query}", media.id),
+                cover_url: media.cover_url,
+                expires: chrono::Utc::now().timestamp() + SIGNED_URL_EXPIRES_SECONDS,
+            }))
+        }
+        None => Err(APIError::with(NoFound).note("No found media with id!")),
     }
-    match state.library_system.get_media_file_by_id(info.0).await {
+}
+
+#[utoipa::path(
+    tag = "media",
+    params(MediaSignature),
+    responses(
+        (status = 200, description = "The media file.", body = [u8], content_type = "application/octet-stream"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/media_file/{id}")]
+pub async fn get_media_file(state: State, info: web::Path<(i64,)>, permission: UserPermission, signature: Option<web::Query<MediaSignature>>) -> Result<NamedFile, APIError> {
+    let owner = get_media_owner(&state, SignedMedia::File, info.0, &permission, signature).await?;
+    match state.library_system.get_media_file_by_id(info.0, &owner.hidden_libraries).await? {
         Some(media) => NamedFile::open_async(&media).await.map_err(|err| APIError::with(Unexpected).note(err.to_string())),
         None => Err(APIError::with(NoFound).note("Can't get target media by hash id.")),
     }
 }
 
+#[utoipa::path(
+    tag = "media",
+    params(MediaSignature),
+    responses(
+        (status = 200, description = "The cover image.", body = [u8], content_type = "image/*"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[get("/media_cover/{id}")]
-pub async fn get_media_cover(state: State, info: web::Path<(i64,)>, permission: UserPermission) -> Result<NamedFile, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
-    }
+pub async fn get_media_cover(state: State, info: web::Path<(i64,)>, permission: UserPermission, signature: Option<web::Query<MediaSignature>>) -> Result<NamedFile, APIError> {
+    let owner = get_media_owner(&state, SignedMedia::Cover, info.0, &permission, signature).await?;
 
-    match state.library_system.get_media_cover_file_by_id(info.0).await {
+    match state.library_system.get_media_cover_file_by_id(info.0, &owner.hidden_libraries).await? {
         Some(img) => {
             if img.is_file() {
                 NamedFile::open_async(&img).await.map_err(|err| APIError::with(Unexpected).note(err.to_string()))
@@ -129,118 +230,330 @@ pub async fn get_media_cover(state: State, info: web::Path<(i64,)>, permission:
     }
 }
 
+#[utoipa::path(
+    tag = "media",
+    responses(
+        (status = 200, description = "Succeeded.", body = PubMediaInfo),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[get("/media_info/{id}")]
-pub async fn get_media_info(state: State, info: web::Path<(i64,)>, permission: UserPermission) -> Result<Json<PubMediaInfo>, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
-    }
-    match state.library_system.get_media_info_by_id(info.0).await {
-        Some(info) => Ok(Json(info.into())),
+pub async fn get_media_info(state: State, info: web::Path<(i64,)>, permission: Require<guard::Logged>) -> Result<Json<PubMediaInfo>, APIError> {
+    let owner = permission.get_owner()?;
+    match state.library_system.get_media_info_by_id(info.0, &owner.hidden_libraries).await? {
+        Some(info) => Ok(Json(sign_media_cover(&state, info.into(), &owner))),
         None => Err(APIError::with(NoFound).note("No found media with id!")),
     }
 }
 
+#[utoipa::path(
+    tag = "media",
+    params(dto::GetMediasQuery),
+    responses(
+        (status = 200, description = "Succeeded.", body = ListSlice<PubMediaInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[get("/medias")]
-pub async fn get_medias(state: State, query: web::Query<dto::GetMediasQuery>, permission: UserPermission) -> Result<Json<dto::ListSlice<PubMediaInfo>>, APIError> {
-    use crate::library_system::model::Source;
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
-    }
-    let source = Source::parse(&query.source, query.filter.as_ref().map(|f| f.as_str()));
-    let to_search = query.to_search.as_ref().map(|s| s.as_str());
-    let medias = state.library_system.get_medias(source, to_search, query.index, query.limit).await;
+pub async fn get_medias(state: State, query: web::Query<dto::GetMediasQuery>, permission: Require<guard::Logged>) -> Result<Json<dto::ListSlice<PubMediaInfo>>, APIError> {
+    let owner = permission.get_owner()?;
+    let source = Source::parse(&query.source, query.filter.as_deref());
+    let to_search = query.to_search.as_deref();
+    let medias = state.library_system.get_medias(source, to_search, query.index, query.limit, &owner.hidden_libraries).await?;
 
-    let total = state.library_system.get_total_media(source, to_search).await;
+    let total = state.library_system.get_total_media(source, to_search, &owner.hidden_libraries).await?;
     Ok(Json(dto::ListSlice {
-        items: medias.into_iter().map(|v| v.into()).collect(),
+        items: medias.into_iter().map(|v| sign_media_cover(&state, v.into(), &owner)).collect(),
         total,
     }))
 }
 
+#[utoipa::path(
+    tag = "media",
+    params(dto::GetArchiveQuery),
+    responses(
+        (status = 200, description = "The ZIP archive of medias.", body = [u8], content_type = "application/zip"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/medias/archive")]
+pub async fn get_medias_archive(state: State, query: web::Query<dto::GetArchiveQuery>, permission: Require<guard::Download>) -> Result<HttpResponse, APIError> {
+    let owner = permission.get_owner()?;
+    let source = Source::parse(&query.source, query.filter.as_deref());
+    let to_search = query.to_search.as_deref();
+    let total = state.library_system.get_total_media(source, to_search, &owner.hidden_libraries).await?;
+    if total == 0 {
+        return Err(APIError::with(NoFound).note("No found medias to archive."));
+    }
+    let medias = state.library_system.get_medias(source, to_search, 0, total, &owner.hidden_libraries).await?;
+
+    // The archive is written into one side of the pipe while the response reads the other side.
+    let (reader, writer) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        if let Err(err) = archive::write_medias_archive(&medias, writer).await {
+            error!("Write medias archive failed: {err}");
+        }
+    });
+    let file_name = match query.filter.as_deref() {
+        Some(filter) if !filter.is_empty() => format!("{filter}.zip"),
+        _ => "medias.zip".to_owned(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name)],
+        })
+        .streaming(ReaderStream::new(reader)))
+}
+
+#[utoipa::path(
+    tag = "media",
+    params(dto::GetSourcesQuery),
+    responses(
+        (status = 200, description = "Succeeded.", body = ListSlice<SourceInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[get("/sources")]
-pub async fn get_sources(state: State, query: web::Query<dto::GetSourcesQuery>, permission: UserPermission) -> Result<Json<ListSlice<SourceInfo>>, APIError> {
-    use crate::library_system::model::Source;
+pub async fn get_sources(state: State, query: web::Query<dto::GetSourcesQuery>, permission: Require<guard::Logged>) -> Result<Json<ListSlice<SourceInfo>>, APIError> {
+    let owner = permission.get_owner()?;
     let source = Source::parse(&query.source, Some(""));
-    let to_search = query.to_search.as_ref().map(|s| s.as_str());
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
-    }
-    let items = state.library_system.get_sources(source, to_search, query.index, query.limit).await;
-    let total = state.library_system.get_total_source(source).await;
+    let to_search = query.to_search.as_deref();
+    let items = state.library_system.get_sources(source, to_search, query.index, query.limit, &owner.hidden_libraries).await?;
+    let total = state.library_system.get_total_source(source, &owner.hidden_libraries).await?;
     Ok(Json(ListSlice { items, total }))
 }
 
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded.", body = UserInfo),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[get("/users/{username}")]
-pub async fn get_user(state: State, info: web::Path<(String,)>, permission: UserPermission) -> Result<Json<UserInfo>, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
+pub async fn get_user(state: State, info: web::Path<(String,)>, _permission: Require<guard::ManageUsers>) -> Result<Json<UserInfo>, APIError> {
+    if !state.user_system.exists_user(&info.0).await? {
+        return Err(APIError::with(NoFoundUser).note("No found user with username!"));
     }
+    Ok(Json(state.user_system.get_user(&info.0).await?))
+}
+
+#[utoipa::path(
+    tag = "user",
+    params(dto::GetUsersQuery),
+    responses(
+        (status = 200, description = "Succeeded.", body = ListSlice<UserInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/users")]
+pub async fn get_users(state: State, _permission: Require<guard::ManageUsers>, query: web::Query<dto::GetUsersQuery>) -> Result<Json<ListSlice<UserInfo>>, APIError> {
+    let to_search = query.to_search.as_deref();
+    let items = state.user_system.get_users(query.index, query.limit, to_search).await?;
+    let total = state.user_system.get_total_user(to_search).await?;
+    Ok(Json(ListSlice { items, total }))
+}
+
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[put("/users/{username}/role")]
+pub async fn update_user_role(state: State, info: web::Path<(String,)>, permission: Require<guard::ManageUsers>, to_update: Json<RoleToUpdate>) -> Result<HttpResponse, APIError> {
+    if to_update.role == ADMIN_ROLE && !permission.is_admin() {
+        return Err(APIError::with(NoPermission).note("Only admin can assign the admin role."));
+    }
+    if !state.user_system.exists_user(&info.0).await? {
+        return Err(APIError::with(NoFoundUser).note("No found user with username!"));
+    }
+    check_admin_target(&permission, &state.user_system.get_user(&info.0).await?)?;
+    let Some(role) = state.user_system.get_role(&to_update.role).await? else {
+        return Err(APIError::with(NoFound).note("No found role with name!"));
+    };
+    // Others than admin can't raise the capabilities, neither of themselves nor by the role given to others.
     if !permission.is_admin() {
-        return Err(APIError::with(NoPermission).note("Only admin can access."));
+        let owner = permission.get_owner()?;
+        if owner.username == info.0 {
+            return Err(APIError::with(NoPermission).note("Only admin can change the own role."));
+        }
+        if let Some(capability) = role.capabilities.iter().find(|capability| !owner.can(**capability)) {
+            return Err(APIError::with(NoPermission)
+                .note("Can't assign the role with capabilities you don't have.")
+                .details(json!({ "capability": capability })));
+        }
     }
-
-    if !state.user_system.exists_user(&info.0).await {
-        return Err(APIError::with(NoFoundUser));
+    match state.user_system.update_user_role(&info.0, &to_update.role).await? {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(APIError::with(NoFoundUser).note("No found user with username!")),
     }
-    Ok(Json(state.user_system.get_user(&info.0).await))
 }
 
-#[get("/users")]
-pub async fn get_users(state: State, permission: UserPermission, query: web::Query<dto::GetUsersQuery>) -> Result<Json<ListSlice<UserInfo>>, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[put("/users/{username}/permissions")]
+pub async fn update_user_permissions(state: State, info: web::Path<(String,)>, permission: Require<guard::ManageUsers>, to_update: Json<UserPermissionsToUpdate>) -> Result<HttpResponse, APIError> {
+    if !state.user_system.exists_user(&info.0).await? {
+        return Err(APIError::with(NoFoundUser).note("No found user with username!"));
+    }
+    check_admin_target(&permission, &state.user_system.get_user(&info.0).await?)?;
+    match state.user_system.update_user_permissions(&info.0, &to_update).await? {
+        true => Ok(HttpResponse::Ok().finish()),
+        false => Err(APIError::with(NoFoundUser).note("No found user with username!")),
     }
-    if !permission.is_admin() {
-        return Err(APIError::with(NoPermission).note("Only admin can access."));
-    }
-    let to_search = query.to_search.as_ref().map(|s| s.as_str());
-    let items = state.user_system.get_users(query.index, query.limit, to_search).await;
-    let total = state.user_system.get_total_user(to_search).await;
-    Ok(Json(ListSlice { items, total }))
 }
 
-#[delete("/users/{username}")]
-pub async fn delete_user(state: State, info: web::Path<(String,)>, permission: UserPermission) -> Result<HttpResponse, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded.", body = Vec<LibraryAccess>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/users/{username}/libraries")]
+pub async fn get_user_libraries(state: State, info: web::Path<(String,)>, _permission: Require<guard::ManageUsers>) -> Result<Json<Vec<LibraryAccess>>, APIError> {
+    if !state.user_system.exists_user(&info.0).await? {
+        return Err(APIError::with(NoFoundUser).note("No found user with username!"));
+    }
+    let user = state.user_system.get_user(&info.0).await?;
+    let libraries = state
+        .config
+        .get()
+        .libraries
+        .iter()
+        .map(|library| LibraryAccess {
+            allowed: !user.hidden_libraries.contains(&library.title),
+            library: library.title.clone(),
+        })
+        .collect();
+    Ok(Json(libraries))
+}
+
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[put("/users/{username}/libraries")]
+pub async fn update_user_library(state: State, info: web::Path<(String,)>, permission: Require<guard::ManageUsers>, to_update: Json<LibraryAccess>) -> Result<HttpResponse, APIError> {
+    if !state.user_system.exists_user(&info.0).await? {
+        return Err(APIError::with(NoFoundUser).note("No found user with username!"));
+    }
+    if !state.config.get().libraries.iter().any(|library| library.title == to_update.library) {
+        return Err(APIError::with(NoFound).note("No found library with title!"));
+    }
+    let user = state.user_system.get_user(&info.0).await?;
+    check_admin_target(&permission, &user)?;
+    state.user_system.set_library_access(&user, &to_update.library, to_update.allowed).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "role",
+    responses(
+        (status = 200, description = "Succeeded.", body = Vec<RoleInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/roles")]
+pub async fn get_roles(state: State, _permission: Require<guard::ManageUsers>) -> Result<Json<Vec<RoleInfo>>, APIError> {
+    Ok(Json(state.user_system.get_roles().await?))
+}
+
+#[utoipa::path(
+    tag = "role",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[put("/roles")]
+pub async fn save_role(state: State, _permission: Require<guard::Admin>, role: Json<RoleInfo>) -> Result<HttpResponse, APIError> {
+    if role.name.is_empty() || !role.name.is_ascii() {
+        return Err(APIError::with(Validation).note("Role name must be non-empty ascii characters.").details(json!({ "field": "name" })));
+    }
+    if role.name == ADMIN_ROLE {
+        return Err(APIError::with(NoPermission).note("Can't modify the admin role!"));
+    }
+    state.user_system.save_role(&role).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[utoipa::path(
+    tag = "role",
+    responses(
+        (status = 202, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[delete("/roles/{name}")]
+pub async fn delete_role(state: State, info: web::Path<(String,)>, _permission: Require<guard::Admin>) -> Result<HttpResponse, APIError> {
+    if [ADMIN_ROLE, USER_ROLE, GUEST_ROLE].contains(&info.0.as_str()) {
+        return Err(APIError::with(NoPermission).note("Can't delete the builtin role!"));
+    }
+    match state.user_system.delete_role(&info.0).await? {
+        true => Ok(HttpResponse::Accepted().finish()),
+        false => Err(APIError::with(NoFound).note("No found role with name!")),
     }
+}
 
-    if !state.user_system.exists_user(&info.0).await {
-        return Err(APIError::with(NoFoundUser));
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 202, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[delete("/users/{username}")]
+pub async fn delete_user(state: State, info: web::Path<(String,)>, permission: Require<guard::Session>) -> Result<HttpResponse, APIError> {
+    if !state.user_system.exists_user(&info.0).await? {
+        return Err(APIError::with(NoFoundUser).note("No found user with username!"));
     }
 
-    let user = state.user_system.get_user(&info.0).await;
+    let user = state.user_system.get_user(&info.0).await?;
     if user.is_admin {
         return Err(APIError::with(NoPermission).note("Can't delete admin!"));
     }
 
     if permission.have_permission_with(&info.0) {
-        match state.user_system.delete_user(&user).await {
-            Ok(_) => Ok(HttpResponse::Accepted().finish()),
-            Err(err) => Err(APIError::with(Unexpected).note(err.to_string())),
-        }
+        state.user_system.delete_user(&user).await?;
+        Ok(HttpResponse::Accepted().finish())
     } else {
         Err(APIError::with(NoPermission).note("No have permission!".to_owned()))
     }
 }
 
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[put("/users")]
-pub async fn update_user(state: State, permission: UserPermission, to_update: Json<UserToCreate>) -> Result<HttpResponse, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoPermission).note("Please log in first!"));
-    }
-
+pub async fn update_user(state: State, permission: Require<guard::Session>, to_update: Json<UserToCreate>) -> Result<HttpResponse, APIError> {
     if permission.is_guest() {
         return Err(APIError::with(NoPermission).note("Guest can't update information."));
     }
 
     let user = permission.get_owner()?;
-    if state.user_system.exists_user(&user.username).await {
+    if state.user_system.exists_user(&user.username).await? {
         if permission.have_permission_with(&user.username) {
-            if state.user_system.update_user(&user.into(), to_update.0).await {
+            if state.user_system.update_user(&user, to_update.0).await? {
                 Ok(HttpResponse::Ok().finish())
             } else {
-                Err(APIError::with(Unspecified).note("No found user to update."))
+                Err(APIError::with(NoFoundUser).note("No found user to update."))
             }
         } else {
             Err(APIError::with(NoPermission).note("No have permission!".to_owned()))
@@ -250,83 +563,392 @@ pub async fn update_user(state: State, permission: UserPermission, to_update: Js
     }
 }
 
+#[utoipa::path(
+    tag = "user",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[post("/users")]
-pub async fn create_user(state: State, permission: UserPermission, to_create: Json<UserToCreate>) -> Result<HttpResponse, APIError> {
-    if !permission.exists_owner() {
-        return Err(APIError::with(NoFoundUser).note("Please login first."));
-    }
-
-    if !permission.is_admin() {
-        return Err(APIError::with(NoPermission).note("Only admin can create user."));
-    }
-
+pub async fn create_user(state: State, _permission: Require<guard::ManageUsers>, to_create: Json<UserToCreate>) -> Result<HttpResponse, APIError> {
     if to_create.alias.len() < 4 {
-        return Err(APIError::with(Unexpected).note("Alias length must more than 4."));
+        return Err(APIError::with(Validation).note("Alias length must more than 4.").details(json!({ "field": "alias" })));
     }
 
     if to_create.username.len() < 4 {
-        return Err(APIError::with(Unexpected).note("Username length must more than 4."));
+        return Err(APIError::with(Validation).note("Username length must more than 4.").details(json!({ "field": "username" })));
     }
 
     if to_create.password.len() < 8 {
-        return Err(APIError::with(Unexpected).note("Password length must more than 8."));
+        return Err(APIError::with(Validation).note("Password length must more than 8.").details(json!({ "field": "password" })));
     }
 
     if !to_create.username.is_ascii() || !to_create.password.is_ascii() {
-        return Err(APIError::with(Unexpected).note("Found illegal characters in username or password. Ensure it is legal characters."));
+        return Err(APIError::with(Validation).note("Found illegal characters in username or password. Ensure it is legal characters."));
     }
 
     if to_create.username == "guest" {
-        return Err(APIError::with(Unexpected).note("Username can't same with `guest`."));
+        return Err(APIError::with(Conflict).note("Username can't same with `guest`."));
     }
 
-    if !state.user_system.exists_user(&to_create.username).await {
-        match state.user_system.create_user(to_create.0, false).await {
-            Ok(()) => Ok(HttpResponse::Ok().finish()),
-            Err(err) => Err(APIError::with(Unexpected).note(err.to_string())),
-        }
+    if !state.user_system.exists_user(&to_create.username).await? {
+        state.user_system.create_user(to_create.0, USER_ROLE).await?;
+        Ok(HttpResponse::Ok().finish())
     } else {
-        Err(APIError::with(Unspecified).note("Already exists username!".to_owned()))
+        Err(APIError::with(Conflict).note("Already exists username!".to_owned()))
     }
 }
 
-#[get("/login")]
-pub async fn login_user(state: State, query: web::Query<dto::LoginQuery>) -> Result<Json<dto::LoginedResult>, APIError> {
-    match state.user_system.login(&query.username, &query.password).await {
-        Some(token) => {
-            let current = state.user_system.get_user(&query.username).await;
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 200, description = "Succeeded.", body = dto::LoginedResult),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[post("/login")]
+pub async fn login_user(state: State, req: HttpRequest, body: Json<dto::LoginBody>) -> Result<Json<dto::LoginedResult>, APIError> {
+    let ip = client_ip(&req, &state);
+    match state.user_system.login(&body.username, &body.password, &ip).await {
+        Ok(token) => {
+            let current = state.user_system.get_user(&body.username).await?;
             Ok(Json(dto::LoginedResult { current, token }))
         }
-        None => Err(APIError::with(NoFoundUser).note("Please make sure username and password is correct!")),
+        Err(LoginFailure::Throttled(wait)) => {
+            Err(APIError::with(TooManyRequests).note(format!("Too many failed login attempts, please retry after {wait} seconds.")))
+        }
+        Err(LoginFailure::Incorrect) => Err(APIError::with(Unauthorized).note("Please make sure username and password is correct!")),
+        Err(LoginFailure::Database(err)) => Err(err.into()),
     }
 }
 
+#[utoipa::path(
+    tag = "auth",
+    params(dto::GetAuthLogsQuery),
+    responses(
+        (status = 200, description = "Succeeded.", body = ListSlice<AuthLogInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/auth_logs")]
+pub async fn get_auth_logs(state: State, _permission: Require<guard::ManageUsers>, query: web::Query<dto::GetAuthLogsQuery>) -> Result<Json<ListSlice<AuthLogInfo>>, APIError> {
+    let username = query.username.as_deref();
+    let items = state.user_system.get_auth_logs(query.index, query.limit, username).await?;
+    let total = state.user_system.get_total_auth_log(username).await?;
+    Ok(Json(ListSlice { items, total }))
+}
+
+#[utoipa::path(
+    tag = "auth",
+    params(dto::LogoutQuery),
+    responses(
+        (status = 200, description = "Succeeded.", body = bool),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[put("/logout")]
 pub async fn logout_user(state: State, query: web::Query<dto::LogoutQuery>) -> Result<Json<bool>, APIError> {
     Ok(Json(state.user_system.logout(&query.token).await))
 }
 
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 200, description = "Succeeded.", body = UserInfo),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[get("/current_user")]
 pub async fn get_current_user(permission: UserPermission) -> Result<Json<UserInfo>, APIError> {
-    permission.get_owner().map(|user| Json(user))
+    permission.get_owner().map(Json)
 }
 
+#[utoipa::path(
+    tag = "action",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[put("/actions/reload_medias")]
-pub async fn reload_medias(state: State, permission: UserPermission) -> Result<HttpResponse, APIError> {
-    if !permission.is_admin() {
-        Err(APIError::with(NoPermission).note("Only administrator can operate."))
-    } else {
-        state.library_system.reload(&state.plugin_system).await;
-        Ok(HttpResponse::Ok().finish())
-    }
+pub async fn reload_medias(state: State, _permission: Require<guard::TriggerScan>) -> HttpResponse {
+    state.library_system.reload(&state.plugin_system).await;
+    HttpResponse::Ok().finish()
 }
 
+#[utoipa::path(
+    tag = "action",
+    responses(
+        (status = 200, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
 #[put("/actions/reload_plugins")]
-pub async fn reload_plugins(state: State, permission: UserPermission) -> Result<HttpResponse, APIError> {
-    if !permission.is_admin() {
-        Err(APIError::with(NoPermission).note("Only administrator can operate."))
-    } else {
-        state.plugin_system.reload().await;
-        Ok(HttpResponse::Ok().finish())
+pub async fn reload_plugins(state: State, _permission: Require<guard::ManagePlugins>) -> HttpResponse {
+    state.plugin_system.reload().await;
+    HttpResponse::Ok().finish()
+}
+
+#[utoipa::path(
+    tag = "plugin",
+    responses(
+        (status = 200, description = "Succeeded, the rejected plugins are included with the error.", body = Vec<Plugin>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/plugins")]
+pub async fn get_plugins(state: State, _permission: Require<guard::ManagePlugins>) -> Json<Vec<Plugin>> {
+    Json(state.plugin_system.get_plugins().await)
+}
+
+#[utoipa::path(
+    tag = "plugin",
+    responses(
+        (status = 200, description = "Succeeded.", body = Plugin),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/plugins/{name}")]
+pub async fn get_plugin(state: State, _permission: Require<guard::ManagePlugins>, name: web::Path<String>) -> Result<Json<Plugin>, APIError> {
+    match state.plugin_system.get_plugin(&name).await {
+        Some(plugin) => Ok(Json(plugin)),
+        None => Err(APIError::with(NoFound).note(format!("No found the plugin `{name}`."))),
+    }
+}
+
+#[utoipa::path(
+    tag = "plugin",
+    responses(
+        (status = 200, description = "Succeeded, it takes effect from the next scan.", body = Plugin),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[put("/plugins/{name}")]
+pub async fn update_plugin(state: State, permission: Require<guard::ManagePlugins>, name: web::Path<String>, to_update: Json<PluginToUpdate>) -> Result<Json<Plugin>, APIError> {
+    if to_update.granted.is_some() && !permission.is_admin() {
+        return Err(APIError::with(NoPermission).note("Only admin can grant the permissions of plugin."));
+    }
+    let no_found = || APIError::with(NoFound).note(format!("No found the plugin `{name}`."));
+    let plugin = state.plugin_system.get_plugin(&name).await.ok_or_else(no_found)?;
+    let to_update = to_update.into_inner();
+    let config = to_update.config.unwrap_or(plugin.config);
+    if let Some(Err(errors)) = plugin.manifest.as_ref().map(|manifest| manifest.validate_config(&config)) {
+        return Err(APIError::with(Validation)
+            .note("The config doesn't match the schema of plugin.")
+            .details(json!({ "field": "config", "errors": errors })));
+    }
+    let granted = to_update.granted.unwrap_or(plugin.granted);
+    let requested = plugin.manifest.map(|manifest| manifest.permissions).unwrap_or_default();
+    if let Err(err) = requested.check_grant(&granted) {
+        return Err(APIError::with(Validation)
+            .note(format!("Only the permissions requested by the plugin can be granted. {err}"))
+            .details(json!({ "field": "granted" })));
+    }
+    state.plugin_system.update_plugin(&name, to_update.enabled.unwrap_or(plugin.enabled), &config, &granted).await?;
+    state.plugin_system.get_plugin(&name).await.map(Json).ok_or_else(no_found)
+}
+
+#[utoipa::path(
+    tag = "share",
+    responses(
+        (status = 200, description = "Succeeded.", body = ShareInfo),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[post("/shares")]
+pub async fn create_share(state: State, permission: Require<guard::Share>, to_create: Json<ShareToCreate>) -> Result<Json<ShareInfo>, APIError> {
+    let owner = permission.get_owner()?;
+    if to_create.expires_at.is_some_and(|expires_at| expires_at <= chrono::Utc::now().timestamp()) {
+        return Err(APIError::with(Validation).note("The expiry time must be in the future.").details(json!({ "field": "expires_at" })));
+    }
+    if to_create.password.as_ref().is_some_and(|pass| pass.is_empty()) {
+        return Err(APIError::with(Validation).note("Password can't be empty.").details(json!({ "field": "password" })));
+    }
+
+    let hidden = &owner.hidden_libraries;
+    let mut to_create = to_create.into_inner();
+    let exists_target = match to_create.kind {
+        ShareKind::Media => {
+            let media = match to_create.target.parse() {
+                Ok(id) => state.library_system.get_media_info_by_id(id, hidden).await?,
+                Err(_) => None,
+            };
+            // The media id changes on full scan, share the media by its path.
+            media.map(|media| to_create.target = media.path.to_string_lossy().into_owned()).is_some()
+        }
+        ShareKind::Album => state.library_system.get_total_media(Source::Album(&to_create.target), None, hidden).await? > 0,
+        ShareKind::Category => state.library_system.get_total_media(Source::Category(&to_create.target), None, hidden).await? > 0,
+    };
+    if !exists_target {
+        return Err(APIError::with(NoFound).note("No found the target to share."));
     }
+
+    match state.share_system.create_share(&owner, to_create).await {
+        Ok(share) => Ok(Json(share)),
+        Err(err) => Err(APIError::with(Unexpected).note(err.to_string())),
+    }
+}
+
+#[utoipa::path(
+    tag = "share",
+    params(dto::GetSharesQuery),
+    responses(
+        (status = 200, description = "Succeeded.", body = ListSlice<ShareInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/shares")]
+pub async fn get_shares(state: State, permission: Require<guard::Logged>, query: web::Query<dto::GetSharesQuery>) -> Result<Json<ListSlice<ShareInfo>>, APIError> {
+    let owner = permission.get_owner()?;
+    // User managers can manage the shares of all users.
+    let owner = if permission.can(Capability::ManageUsers) { None } else { Some(owner.username.as_str()) };
+    let items = state.share_system.get_shares(query.index, query.limit, owner).await?;
+    let total = state.share_system.get_total_share(owner).await?;
+    Ok(Json(ListSlice { items, total }))
+}
+
+#[utoipa::path(
+    tag = "share",
+    responses(
+        (status = 202, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[delete("/shares/{token}")]
+pub async fn delete_share(state: State, info: web::Path<(String,)>, permission: Require<guard::Logged>) -> Result<HttpResponse, APIError> {
+    let share = match state.share_system.get_share(&info.0).await? {
+        Some(share) => share,
+        None => return Err(APIError::with(NoFound).note("No found share with token!")),
+    };
+    if !permission.have_permission_with(&share.owner) {
+        return Err(APIError::with(NoPermission).note("No have permission!"));
+    }
+    match state.share_system.delete_share(&share.token).await {
+        Ok(_) => Ok(HttpResponse::Accepted().finish()),
+        Err(err) => Err(APIError::with(Unexpected).note(err.to_string())),
+    }
+}
+
+#[utoipa::path(
+    tag = "api_key",
+    responses(
+        (status = 200, description = "Succeeded.", body = ApiKeyCreated),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[post("/api_keys")]
+pub async fn create_api_key(state: State, permission: Require<guard::Session>, to_create: Json<ApiKeyToCreate>) -> Result<Json<ApiKeyCreated>, APIError> {
+    let owner = permission.get_owner()?;
+    if permission.is_guest() {
+        return Err(APIError::with(NoPermission).note("Guest can't create api key."));
+    }
+    if to_create.name.trim().is_empty() {
+        return Err(APIError::with(Validation).note("Name of api key can't be empty.").details(json!({ "field": "name" })));
+    }
+    if to_create.expires_at.is_some_and(|expires_at| expires_at <= chrono::Utc::now().timestamp()) {
+        return Err(APIError::with(Validation).note("The expiry time must be in the future.").details(json!({ "field": "expires_at" })));
+    }
+    Ok(Json(state.user_system.create_api_key(&owner, to_create.0).await?))
+}
+
+#[utoipa::path(
+    tag = "api_key",
+    responses(
+        (status = 200, description = "Succeeded.", body = Vec<ApiKeyInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/api_keys")]
+pub async fn get_api_keys(state: State, permission: Require<guard::Logged>) -> Result<Json<Vec<ApiKeyInfo>>, APIError> {
+    let owner = permission.get_owner()?;
+    Ok(Json(state.user_system.get_api_keys(&owner).await?))
+}
+
+#[utoipa::path(
+    tag = "api_key",
+    responses(
+        (status = 202, description = "Succeeded."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[delete("/api_keys/{id}")]
+pub async fn delete_api_key(state: State, info: web::Path<(i64,)>, permission: Require<guard::Logged>) -> Result<HttpResponse, APIError> {
+    let owner = permission.get_owner()?;
+    match state.user_system.delete_api_key(&owner, info.0).await? {
+        true => Ok(HttpResponse::Accepted().finish()),
+        false => Err(APIError::with(NoFound).note("No found api key with id!")),
+    }
+}
+
+/// Directory of backups, or the error if it's not configured.
+fn backup_dir(state: &State) -> Result<std::path::PathBuf, APIError> {
+    state
+        .config
+        .get()
+        .backup_dir()
+        .ok_or_else(|| APIError::with(Validation).note("No backup directory, set the `data_path` or `backup.dir` first."))
+}
+
+#[utoipa::path(
+    tag = "backup",
+    responses(
+        (status = 200, description = "Succeeded.", body = BackupInfo),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[post("/backups")]
+pub async fn create_backup(state: State, _permission: Require<guard::Admin>) -> Result<Json<BackupInfo>, APIError> {
+    let dir = backup_dir(&state)?;
+    let info = backup::backup(&state.db, &dir).await.map_err(|err| {
+        error!("Backup the database failed! {err:#}");
+        APIError::with(Unexpected).note("Backup the database failed.")
+    })?;
+    let retention = state.config.get().backup.as_ref().map(|backup| backup.retention);
+    if let Some(retention) = retention {
+        if let Err(err) = backup::rotate(&dir, retention).await {
+            warn!("Rotate the backups failed! {err:#}");
+        }
+    }
+    Ok(Json(info))
+}
+
+#[utoipa::path(
+    tag = "backup",
+    responses(
+        (status = 200, description = "Succeeded, the newest first.", body = Vec<BackupInfo>),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/backups")]
+pub async fn get_backups(state: State, _permission: Require<guard::Admin>) -> Result<Json<Vec<BackupInfo>>, APIError> {
+    let dir = backup_dir(&state)?;
+    let backups = backup::list_backups(&dir).await.map_err(|err| {
+        error!("List the backups failed! {err:#}");
+        APIError::with(Unexpected).note("List the backups failed.")
+    })?;
+    Ok(Json(backups))
+}
+
+#[utoipa::path(
+    tag = "backup",
+    responses(
+        (status = 200, description = "The database file.", content_type = "application/octet-stream"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/backups/{name}")]
+pub async fn download_backup(state: State, req: HttpRequest, info: web::Path<(String,)>, _permission: Require<guard::Admin>) -> Result<HttpResponse, APIError> {
+    let name = &info.0;
+    if !backup::is_backup_name(name) {
+        return Err(APIError::with(NoFound).note("No found the backup!"));
+    }
+    let path = backup_dir(&state)?.join(name);
+    let file = NamedFile::open_async(&path).await.map_err(|_| APIError::with(NoFound).note("No found the backup!"))?;
+    Ok(file
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(name.to_owned())],
+        })
+        .into_response(&req))
 }
@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-use crate::{library_system::model::MediaInfo, user_system::model::UserInfo};
+use crate::{
+    library_system::model::MediaInfo, share_system::model::ShareInfo,
+    user_system::model::UserInfo,
+};
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct ServerInfo {
     pub version: &'static str,
     pub author: &'static str,
@@ -10,9 +14,31 @@ pub struct ServerInfo {
     pub admin_required: bool,
     pub guest_enable: bool,
     pub guest_password_required: bool,
+    pub oidc_enable: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Body of `/healthz` and `/readyz`, the status is up only if all components are up.
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub status: HealthStatus,
+    pub components: std::collections::BTreeMap<&'static str, ComponentHealth>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(flatten)]
+    pub details: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 pub struct PubMediaInfo {
     pub id: i64,
     pub title: String,
@@ -33,13 +59,14 @@ pub struct PubMediaInfo {
     pub file_type: String,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 pub struct ListSlice<T> {
     pub items: Vec<T>,
     pub total: usize,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetSourcesQuery {
     pub limit: usize,
     pub index: usize,
@@ -47,7 +74,8 @@ pub struct GetSourcesQuery {
     pub to_search: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetMediasQuery {
     pub limit: usize,
     pub index: usize,
@@ -56,14 +84,23 @@ pub struct GetMediasQuery {
     pub to_search: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetArchiveQuery {
+    pub source: String,
+    pub filter: Option<String>,
+    pub to_search: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetUsersQuery {
     pub limit: usize,
     pub index: usize,
     pub to_search: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ToSetup {
     pub alias: String,
     pub username: String,
@@ -72,39 +109,53 @@ pub struct ToSetup {
     pub guest_password: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct LoginQuery {
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LoginBody {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct LoginedResult {
     pub current: UserInfo,
     pub token: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetAuthLogsQuery {
+    pub limit: usize,
+    pub index: usize,
+    pub username: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct LogoutQuery {
     pub token: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SearchUserQuery {
-    pub content: String,
-    pub index: usize,
-    pub limit: usize,
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SignedMediaUrl {
+    pub file_url: String,
+    pub cover_url: Option<String>,
+    pub expires: i64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AuthQuery {
-    pub auth: String,
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GetSharesQuery {
+    pub limit: usize,
+    pub index: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct GetPLuginQuery {
-    pub index: usize,
-    pub limit: usize,
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ShareDetail {
+    pub share: ShareInfo,
+    pub password_required: bool,
+    /// Query string of the signed access to append to the media urls of a protected share.
+    pub access: Option<String>,
+    pub medias: Vec<PubMediaInfo>,
 }
 
 impl From<MediaInfo> for PubMediaInfo {
@@ -1,20 +1,57 @@
 use std::fmt::{self, Display};
 
-use actix_web::{http::StatusCode, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::Serialize;
+use utoipa::ToSchema;
 
-#[derive(Debug)]
+use crate::db::DbError;
+
+#[derive(Debug, Clone, Copy)]
 pub enum APIErrorType {
+    /// Not logged in or the credential is invalid.
+    Unauthorized,
+    /// Logged in but not allowed.
     NoPermission,
     NoFound,
-    Unspecified,
     NoFoundUser,
+    /// Conflict with the exists resource.
+    Conflict,
+    /// The request is well-formed but the values are invalid.
+    Validation,
+    TooManyRequests,
     Unexpected,
 }
 
+impl APIErrorType {
+    /// Stable code of the error, clients should match it instead of the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            APIErrorType::Unauthorized => "unauthorized",
+            APIErrorType::NoPermission => "no_permission",
+            APIErrorType::NoFound => "no_found",
+            APIErrorType::NoFoundUser => "no_found_user",
+            APIErrorType::Conflict => "conflict",
+            APIErrorType::Validation => "validation",
+            APIErrorType::TooManyRequests => "too_many_requests",
+            APIErrorType::Unexpected => "unexpected",
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct APIError {
     error_type: APIErrorType,
     note: Option<String>,
+    details: Option<serde_json::Value>,
+}
+
+/// Body of the error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct APIErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
 }
 
 impl APIError {
@@ -22,21 +59,29 @@ impl APIError {
         Self {
             error_type,
             note: None,
+            details: None,
         }
     }
     pub fn note<T: Into<String>>(mut self, note: T)-> Self {
         self.note = Some(note.into());
         self
     }
+    pub fn details<T: Serialize>(mut self, details: T) -> Self {
+        self.details = serde_json::to_value(details).ok();
+        self
+    }
 }
 
 impl Display for APIErrorType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            APIErrorType::Unauthorized => write!(f, "Unauthorized"),
             APIErrorType::NoPermission => write!(f, "NoPermission"),
             APIErrorType::NoFound => write!(f, "NoFound"),
-            APIErrorType::Unspecified => write!(f, "Unspecified"),
             APIErrorType::NoFoundUser => write!(f, "NoFoundUser"),
+            APIErrorType::Conflict => write!(f, "Conflict"),
+            APIErrorType::Validation => write!(f, "Validation"),
+            APIErrorType::TooManyRequests => write!(f, "TooManyRequests"),
             APIErrorType::Unexpected => write!(f, "Unexpected"),
         }
     }
@@ -45,16 +90,8 @@ impl Display for APIErrorType {
 impl Display for APIError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self.note {
-            Some(note) => match self.error_type {
-                APIErrorType::NoPermission => write!(f, "NoPermission: {}", note),
-                APIErrorType::NoFound => write!(f, "NoFound: {}", note),
-                APIErrorType::Unspecified => write!(f, "Unspecified: {}", note),
-                APIErrorType::NoFoundUser => write!(f, "NoFoundUser: {}", note),
-                APIErrorType::Unexpected => write!(f, "Unexpected: {}", note),
-            },
-            None=> {
-                write!(f, "{}", self.error_type)
-            }
+            Some(note) => write!(f, "{}: {}", self.error_type, note),
+            None => write!(f, "{}", self.error_type),
         }
     }
 }
@@ -62,11 +99,34 @@ impl Display for APIError {
 impl ResponseError for APIError {
     fn status_code(&self) -> StatusCode {
         match self.error_type {
+            APIErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            APIErrorType::NoPermission => StatusCode::FORBIDDEN,
             APIErrorType::NoFound => StatusCode::NOT_FOUND,
-            APIErrorType::NoPermission => StatusCode::NOT_ACCEPTABLE,
-            APIErrorType::Unspecified => StatusCode::INTERNAL_SERVER_ERROR,
             APIErrorType::NoFoundUser => StatusCode::NOT_FOUND,
-            APIErrorType::Unexpected => StatusCode::EXPECTATION_FAILED,
+            APIErrorType::Conflict => StatusCode::CONFLICT,
+            APIErrorType::Validation => StatusCode::UNPROCESSABLE_ENTITY,
+            APIErrorType::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            APIErrorType::Unexpected => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(APIErrorBody {
+            code: self.error_type.code(),
+            message: self.note.clone().unwrap_or_else(|| self.error_type.to_string()),
+            details: self.details.clone(),
+        })
+    }
+}
+
+impl From<DbError> for APIError {
+    fn from(value: DbError) -> Self {
+        match value {
+            DbError::NotFound(_) => APIError::with(APIErrorType::NoFound).note(value.to_string()),
+            _ => {
+                tracing::error!("{value}");
+                APIError::with(APIErrorType::Unexpected).note("Database error.")
+            }
         }
     }
 }
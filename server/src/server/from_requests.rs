@@ -1,13 +1,15 @@
-use std::{future::Future, pin::Pin};
+use std::{future::Future, marker::PhantomData, net::IpAddr, ops::Deref, pin::Pin};
 
-use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
 use futures_util::future::ok;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
-use crate::user_system::model::UserInfo;
+use crate::{
+    config::ProxyAuthConfig,
+    user_system::model::{Capability, UserInfo},
+};
 
 use super::{
-    dto,
     error::{APIError, APIErrorType},
     AppState,
 };
@@ -15,6 +17,8 @@ use super::{
 #[derive(Debug, Clone)]
 pub struct UserPermission {
     owner: Option<UserInfo>,
+    /// Authenticated by an API key instead of a session.
+    by_api_key: bool,
 }
 
 impl UserPermission {
@@ -27,7 +31,7 @@ impl UserPermission {
     }
 
     pub fn have_permission_with(&self, username: &str) -> bool {
-        if self.is_admin() {
+        if self.can(Capability::ManageUsers) {
             true
         } else if let Some(owner) = &self.owner {
             owner.username == username
@@ -39,16 +43,24 @@ impl UserPermission {
     pub fn get_owner(&self) -> Result<UserInfo, APIError> {
         match &self.owner {
             Some(owner) => Ok(owner.clone()),
-            None => Err(APIError::with(APIErrorType::NoPermission).note("User is not logging!")),
+            None => Err(APIError::with(APIErrorType::Unauthorized).note("User is not logging!")),
         }
     }
 
+    pub fn can(&self, capability: Capability) -> bool {
+        self.owner.as_ref().is_some_and(|owner| owner.can(capability))
+    }
+
     pub fn exists_owner(&self) -> bool {
         self.owner.is_some()
     }
 
     pub fn is_guest(&self) -> bool {
-        self.exists_owner() && self.owner.as_ref().unwrap().username == "guest"
+        self.owner.as_ref().is_some_and(|owner| owner.is_guest())
+    }
+
+    pub fn is_by_api_key(&self) -> bool {
+        self.by_api_key
     }
 }
 
@@ -59,29 +71,206 @@ impl FromRequest for UserPermission {
     fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
         let state = req.app_data::<web::Data<AppState>>().unwrap().clone();
 
-        let auth_query = web::Query::<dto::AuthQuery>::from_query(req.query_string());
-        let auth = if let Ok(q) = auth_query {
-            Some(q.auth.to_owned())
-        } else {
-            'token: {
-                if let Some(header) = req.headers().get("x-authorization") {
-                    if let Ok(str) = header.to_str() {
-                        break 'token Some(str.to_owned());
-                    } else {
-                        error!("Request header to_str failed!");
-                    }
-                };
-                None
-            }
+        let api_key = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(|key| key.trim().to_owned());
+        if let Some(key) = api_key {
+            return Box::pin(async move {
+                info!("Api key trying verify..");
+                let owner = state.user_system.verify_api_key(&key).await.map_err(APIError::from)?;
+                Ok(UserPermission { owner, by_api_key: true })
+            });
+        }
+
+        let auth = 'token: {
+            if let Some(header) = req.headers().get("x-authorization") {
+                if let Ok(str) = header.to_str() {
+                    break 'token Some(str.to_owned());
+                } else {
+                    error!("Request header to_str failed!");
+                }
+            };
+            None
         };
         if let Some(auth) = auth {
-            return Box::pin(async move {
-                info!("Token `{auth}` trying verify..");
+            Box::pin(async move {
+                info!("Token trying verify..");
                 let owner = state.user_system.verify(&auth).await;
-                Ok(UserPermission { owner })
-            });
+                Ok(UserPermission { owner, by_api_key: false })
+            })
+        } else if let Some(username) = get_proxy_username(req, &state) {
+            Box::pin(async move {
+                let config = state.config.get();
+                let create_role = config.proxy_auth.as_ref().and_then(|c| c.create_role.as_deref());
+                let owner = match state.user_system.get_proxy_user(&username, create_role).await {
+                    Ok(owner) => owner,
+                    Err(err) => {
+                        error!("Get user of reverse proxy failed: {err}");
+                        None
+                    }
+                };
+                Ok(UserPermission { owner, by_api_key: false })
+            })
         } else {
-            Box::pin(ok(UserPermission { owner: None }))
+            Box::pin(ok(UserPermission { owner: None, by_api_key: false }))
+        }
+    }
+}
+
+/// Whether the request come from the trusted proxies, requests of unix socket have no peer address.
+fn from_trusted_proxy(req: &HttpRequest, config: &ProxyAuthConfig) -> bool {
+    match req.peer_addr() {
+        Some(peer) => config.trusted_proxies.iter().any(|net| net.contains(&peer.ip())),
+        None => config.trust_unix_socket,
+    }
+}
+
+/// Get the username authenticated by reverse proxy, only if the request come from the trusted proxies.
+fn get_proxy_username(req: &HttpRequest, state: &AppState) -> Option<String> {
+    let config = state.config.get();
+    let config = config.proxy_auth.as_ref()?;
+    let username = req.headers().get(config.header.as_str())?.to_str().ok()?.trim();
+    if !from_trusted_proxy(req, config) {
+        let peer = req.peer_addr().map_or("unix socket".to_owned(), |addr| addr.ip().to_string());
+        warn!("Ignored the `{}` header from untrusted address {peer}.", config.header);
+        return None;
+    }
+    (!username.is_empty()).then(|| username.to_owned())
+}
+
+/// Get the address of client, it is taken from the `X-Forwarded-For` header if the request come from the trusted proxies.
+pub fn client_ip(req: &HttpRequest, state: &AppState) -> String {
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    let config = state.config.get();
+    let Some(config) = config.proxy_auth.as_ref().filter(|config| from_trusted_proxy(req, config)) else {
+        return peer.map(|peer| peer.to_string()).unwrap_or_default();
+    };
+    let is_trusted = |ip: &IpAddr| config.trusted_proxies.iter().any(|net| net.contains(ip));
+    // Every proxy appends the address it received from, the last untrusted one is the client.
+    let forwarded: Vec<IpAddr> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(ip))
+        .or(forwarded.first())
+        .copied()
+        .or(peer)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default()
+}
+
+/// Requirement checked by the [`Require`] extractor.
+pub trait Guard {
+    /// Whether the request authenticated by an API key can pass.
+    const ALLOW_API_KEY: bool = true;
+
+    fn check(owner: &UserInfo) -> Result<(), APIError>;
+}
+
+/// Guards of [`Require`], each one require the owner have the capability with same name.
+pub mod guard {
+    use super::{APIError, APIErrorType, Capability, Guard, UserInfo};
+
+    /// Only require the user is logged in.
+    pub struct Logged;
+
+    impl Guard for Logged {
+        fn check(_owner: &UserInfo) -> Result<(), APIError> {
+            Ok(())
+        }
+    }
+
+    /// Require the user is logged in by a session rather than an API key,
+    /// for managing the credentials and the account itself.
+    pub struct Session;
+
+    impl Guard for Session {
+        const ALLOW_API_KEY: bool = false;
+
+        fn check(_owner: &UserInfo) -> Result<(), APIError> {
+            Ok(())
+        }
+    }
+
+    /// Require the owner is the administrator, for the operations on whole server.
+    pub struct Admin;
+
+    impl Guard for Admin {
+        fn check(owner: &UserInfo) -> Result<(), APIError> {
+            if owner.is_admin {
+                Ok(())
+            } else {
+                Err(APIError::with(APIErrorType::NoPermission).note("Only the administrator can do it."))
+            }
         }
     }
+
+    macro_rules! capability_guards {
+        ($($name:ident),*) => {
+            $(
+                pub struct $name;
+
+                impl Guard for $name {
+                    fn check(owner: &UserInfo) -> Result<(), APIError> {
+                        if owner.can(Capability::$name) {
+                            Ok(())
+                        } else {
+                            Err(APIError::with(APIErrorType::NoPermission)
+                                .note(concat!("Missing the capability `", stringify!($name), "`."))
+                                .details(serde_json::json!({ "capability": Capability::$name })))
+                        }
+                    }
+                }
+            )*
+        };
+    }
+
+    capability_guards!(Stream, Download, Share, TriggerScan, ManageUsers, ManagePlugins);
+}
+
+/// The [`UserPermission`] which the owner is logged in and passed the guard `G`,
+/// otherwise the request is rejected before reaching the handler.
+pub struct Require<G: Guard> {
+    permission: UserPermission,
+    _guard: PhantomData<G>,
+}
+
+impl<G: Guard> Deref for Require<G> {
+    type Target = UserPermission;
+
+    fn deref(&self) -> &Self::Target {
+        &self.permission
+    }
+}
+
+impl<G: Guard> FromRequest for Require<G> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let permission = UserPermission::from_request(req, payload);
+        Box::pin(async move {
+            let permission = permission.await?;
+            match &permission.owner {
+                Some(owner) => G::check(owner)?,
+                None => return Err(APIError::with(APIErrorType::Unauthorized).note("Please log in first!").into()),
+            }
+            if permission.by_api_key && !G::ALLOW_API_KEY {
+                return Err(APIError::with(APIErrorType::NoPermission).note("API key can't do it, please log in.").into());
+            }
+            Ok(Require {
+                permission,
+                _guard: PhantomData,
+            })
+        })
+    }
 }
@@ -0,0 +1,64 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use actix_web::{get, web, HttpResponse};
+use serde_json::json;
+use tokio::time::Instant;
+
+use super::{
+    dto::{ComponentHealth, Health, HealthStatus},
+    AppState,
+};
+
+/// The database is reported down if it can't answer in this time.
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn component(up: bool, details: serde_json::Value) -> ComponentHealth {
+    ComponentHealth {
+        status: if up { HealthStatus::Up } else { HealthStatus::Down },
+        details: match details {
+            serde_json::Value::Object(details) => details,
+            _ => Default::default(),
+        },
+    }
+}
+
+/// Respond `200` if all components are up, otherwise `503`.
+fn respond(components: BTreeMap<&'static str, ComponentHealth>) -> HttpResponse {
+    let up = components.values().all(|component| component.status == HealthStatus::Up);
+    let health = Health {
+        status: if up { HealthStatus::Up } else { HealthStatus::Down },
+        components,
+    };
+    match up {
+        true => HttpResponse::Ok().json(health),
+        false => HttpResponse::ServiceUnavailable().json(health),
+    }
+}
+
+/// Liveness, the process is able to handle requests.
+#[get("/healthz")]
+pub async fn healthz(start: web::Data<Instant>) -> HttpResponse {
+    let server = component(true, json!({ "version": env!("CARGO_PKG_VERSION"), "time_running": start.elapsed().as_secs() }));
+    respond(BTreeMap::from([("server", server)]))
+}
+
+/// Readiness, the database is reachable and the medias and plugins are loaded.
+#[get("/readyz")]
+pub async fn readyz(state: web::Data<AppState>) -> HttpResponse {
+    let database = match tokio::time::timeout(DATABASE_TIMEOUT, sqlx::query("SELECT 1").execute(&state.db)).await {
+        Ok(Ok(_)) => component(true, json!({})),
+        Ok(Err(err)) => component(false, json!({ "error": err.to_string() })),
+        Err(_) => component(false, json!({ "error": "Timed out." })),
+    };
+    let library_system = &state.library_system;
+    let library = component(
+        library_system.is_loaded(),
+        json!({ "loaded": library_system.is_loaded(), "scanning": library_system.is_scanning() }),
+    );
+    let plugin_system = &state.plugin_system;
+    let plugins = component(
+        plugin_system.is_initialized(),
+        json!({ "initialized": plugin_system.is_initialized(), "total": plugin_system.total_plugins().await }),
+    );
+    respond(BTreeMap::from([("database", database), ("library", library), ("plugins", plugins)]))
+}
@@ -0,0 +1,125 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    get,
+    http::header,
+    web::{self, Bytes},
+    Error, HttpRequest, HttpResponse,
+};
+use prometheus::core::Collector;
+use sha2::{Digest, Sha256};
+use tracing::error;
+
+use super::{
+    error::{APIError, APIErrorType},
+    AppState,
+};
+use crate::{library_system::model::Source, metrics::METRICS};
+
+/// Routes of the media files, their responses are counted as streams.
+/// Compared by the suffix, the routes are prefixed by the `base_path`.
+const STREAM_ROUTES: [&str; 3] = ["/api/media_file/{id}", "/share/{token}/media_file/{id}", "/share/{token}/download/{id}"];
+
+/// Count the request by the matched route, it's the `wrap_fn` of the app.
+pub fn track<S>(req: ServiceRequest, srv: &S) -> impl Future<Output = Result<ServiceResponse<TrackedBody>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<BoxBody>, Error = Error>,
+{
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let response = srv.call(req);
+    async move {
+        let res = response.await?;
+        let route = res.request().match_pattern().filter(|route| !route.is_empty()).unwrap_or_else(|| "unmatched".to_owned());
+        let status = res.status();
+        METRICS.http_requests.with_label_values(&[&method, &route, status.as_str()]).inc();
+        METRICS
+            .http_request_duration
+            .with_label_values(&[&method, &route])
+            .observe(start.elapsed().as_secs_f64());
+        let stream = status.is_success() && STREAM_ROUTES.iter().any(|stream_route| route.ends_with(stream_route));
+        if stream {
+            METRICS.active_streams.inc();
+        }
+        Ok(res.map_body(|_, body| TrackedBody { body, stream }))
+    }
+}
+
+/// Body counting the bytes served of stream, the stream is active until it is dropped.
+pub struct TrackedBody {
+    body: BoxBody,
+    stream: bool,
+}
+
+impl MessageBody for TrackedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let poll = Pin::new(&mut self.body).poll_next(cx);
+        if let (true, Poll::Ready(Some(Ok(bytes)))) = (self.stream, &poll) {
+            METRICS.stream_bytes.inc_by(bytes.len() as u64);
+        }
+        poll
+    }
+}
+
+impl Drop for TrackedBody {
+    fn drop(&mut self) {
+        if self.stream {
+            METRICS.active_streams.dec();
+        }
+    }
+}
+
+/// Only served if `metrics` is configured, and to the bearer token of config if it is settled.
+#[get("/metrics")]
+pub async fn get_metrics(req: HttpRequest, state: web::Data<AppState>) -> Result<HttpResponse, APIError> {
+    let config = state.config.get();
+    let Some(metrics_config) = &config.metrics else {
+        return Err(APIError::with(APIErrorType::NoFound).note("The metrics are disabled."));
+    };
+    if let Some(token) = &metrics_config.bearer_token {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        // Compare the digests, so the time taken doesn't tell how much of the token matches.
+        if Sha256::digest(bearer.trim()) != Sha256::digest(token) {
+            return Err(APIError::with(APIErrorType::Unauthorized).note("The bearer token of metrics is invalid."));
+        }
+    }
+    // The gauges are set in place rather than reset, so a concurrent scrape never sees them missing.
+    for library in &config.libraries {
+        match state.library_system.get_total_media(Source::Library(&library.title), None, &[]).await {
+            Ok(total) => METRICS.library_medias.with_label_values(&[&library.title]).set(total as i64),
+            Err(err) => error!("Count the medias of `{}` failed: {err}", library.title),
+        }
+    }
+    let removed: Vec<String> = METRICS
+        .library_medias
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter_map(|metric| metric.get_label().first().map(|label| label.get_value().to_owned()))
+        .filter(|title| !config.libraries.iter().any(|library| &library.title == title))
+        .collect();
+    for title in removed {
+        let _ = METRICS.library_medias.remove_label_values(&[&title]);
+    }
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(METRICS.encode()))
+}
@@ -0,0 +1,191 @@
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
+    get,
+    http::header,
+    web, HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::user_system::oidc::{OidcClient, PENDING_EXPIRES_SECONDS};
+
+use super::error::APIErrorType::*;
+use super::error::*;
+use super::from_requests::client_ip;
+use super::AppState;
+
+type State = web::Data<AppState>;
+
+/// Same as the cookie the web page stored after login.
+const AUTH_COOKIE_NAME: &str = "authorization";
+const AUTH_COOKIE_DAYS: i64 = 90;
+/// Bind the login state to the browser which started it.
+const STATE_COOKIE_NAME: &str = "oidc_state";
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+fn get_client(state: &State) -> Result<&OidcClient, APIError> {
+    state
+        .oidc_client
+        .as_ref()
+        .ok_or_else(|| APIError::with(NoFound).note("Single sign-on is not enabled."))
+}
+
+#[utoipa::path(
+    tag = "auth",
+    responses(
+        (status = 302, description = "Redirect to the provider."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/oidc/login")]
+pub async fn oidc_login(state: State) -> Result<HttpResponse, APIError> {
+    match get_client(&state)?.authorize_url().await {
+        Ok((url, login_state)) => {
+            let cookie = Cookie::build(STATE_COOKIE_NAME, login_state)
+                .path(format!("{}/api/oidc", state.config.get().base_path))
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .max_age(Duration::seconds(PENDING_EXPIRES_SECONDS))
+                .finish();
+            Ok(HttpResponse::Found().cookie(cookie).insert_header((header::LOCATION, url)).finish())
+        }
+        Err(err) => Err(APIError::with(Unexpected).note(format!("Contact the provider failed: {err}"))),
+    }
+}
+
+#[utoipa::path(
+    tag = "auth",
+    params(CallbackQuery),
+    responses(
+        (status = 302, description = "Logged in, redirect to the web page with the token cookie."),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/oidc/callback")]
+pub async fn oidc_callback(state: State, req: HttpRequest, query: web::Query<CallbackQuery>) -> Result<HttpResponse, APIError> {
+    let client = get_client(&state)?;
+    let ip = client_ip(&req, &state);
+    let base_path = state.config.get().base_path.clone();
+    let cookie_state = req.cookie(STATE_COOKIE_NAME).map(|cookie| cookie.value().to_owned());
+    let identity = match (&query.code, &query.state, &query.error) {
+        (Some(code), Some(login_state), None) if cookie_state.as_ref() == Some(login_state) => client.authenticate(code, login_state).await,
+        (Some(_), Some(_), None) => Err(anyhow::anyhow!("The login is not started by this browser.")),
+        (_, _, Some(error)) => Err(anyhow::anyhow!("{error}: {}", query.error_description.as_deref().unwrap_or_default())),
+        _ => Err(anyhow::anyhow!("Missing the code or state.")),
+    };
+    let result = match identity {
+        Ok(identity) => state.user_system.login_oidc(&identity, &ip).await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(token) => {
+            let cookie = Cookie::build(AUTH_COOKIE_NAME, token)
+                .path("/")
+                .same_site(SameSite::Lax)
+                .max_age(Duration::days(AUTH_COOKIE_DAYS))
+                .finish();
+            let mut state_cookie = Cookie::new(STATE_COOKIE_NAME, "");
+            state_cookie.set_path(format!("{base_path}/api/oidc"));
+            state_cookie.make_removal();
+            Ok(HttpResponse::Found()
+                .cookie(cookie)
+                .cookie(state_cookie)
+                .insert_header((header::LOCATION, format!("{base_path}/")))
+                .finish())
+        }
+        Err(err) => {
+            warn!("Single sign-on failed: {err}");
+            state.user_system.record_auth("", &ip, false, Some(&format!("oidc: {err}"))).await?;
+            Err(APIError::with(Unauthorized).note(format!("Single sign-on failed: {err}")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{cookie::Cookie, http::StatusCode, test, web, App};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::{oidc_callback, oidc_login, AUTH_COOKIE_NAME, STATE_COOKIE_NAME};
+    use crate::{
+        config::SharedConfig,
+        library_system::LibrarySystem,
+        plugin_system::PluginSystem,
+        server::AppState,
+        share_system::ShareSystem,
+        user_system::{
+            oidc::mock_idp::{Provider, CODE},
+            UserSystem,
+        },
+    };
+
+    async fn app_state(provider: &Provider) -> web::Data<AppState> {
+        let db = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        let config = serde_json::json!({
+            "libraries": [],
+            "data_path": null,
+            "covers_cached_path": null,
+            "host": "127.0.0.1",
+            "port": 3177,
+            "base_path": "/diosic",
+        });
+        let config = SharedConfig::new(serde_json::from_value(config).unwrap());
+        web::Data::new(AppState {
+            user_system: UserSystem::new(db.clone()).await.unwrap(),
+            share_system: ShareSystem::new(db.clone()).await.unwrap(),
+            oidc_client: Some(provider.client(false).await),
+            library_system: LibrarySystem::new(db.clone(), config.clone()).await.unwrap(),
+            plugin_system: PluginSystem::new(db.clone(), config.clone()).await.unwrap(),
+            config,
+            db,
+        })
+    }
+
+    #[actix_web::test]
+    async fn login_by_the_browser_started_it() {
+        let provider = Provider::start().await;
+        let app = test::init_service(
+            App::new()
+                .app_data(app_state(&provider).await)
+                .service(web::scope("/diosic/api").service(oidc_login).service(oidc_callback)),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/diosic/api/oidc/login").to_request()).await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        let location = res.headers().get("location").unwrap().to_str().unwrap().to_owned();
+        let state_cookie = res.response().cookies().find(|cookie| cookie.name() == STATE_COOKIE_NAME).unwrap().into_owned();
+        assert_eq!(state_cookie.path(), Some("/diosic/api/oidc"));
+        assert_eq!(state_cookie.http_only(), Some(true));
+        let login_state = provider.approve(&location).await;
+        assert_eq!(state_cookie.value(), login_state);
+
+        let callback = format!("/diosic/api/oidc/callback?code={CODE}&state={login_state}");
+        // Without the state cookie, or with the cookie of another login.
+        let res = test::call_service(&app, test::TestRequest::get().uri(&callback).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let another = Cookie::new(STATE_COOKIE_NAME, "state-of-another-login");
+        let res = test::call_service(&app, test::TestRequest::get().uri(&callback).cookie(another).to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(&callback).cookie(state_cookie).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FOUND);
+        assert_eq!(res.headers().get("location").unwrap(), "/diosic/");
+        let cookies: Vec<_> = res.response().cookies().map(Cookie::into_owned).collect();
+        let token = cookies.iter().find(|cookie| cookie.name() == AUTH_COOKIE_NAME).unwrap();
+        assert!(!token.value().is_empty());
+        let removal = cookies.iter().find(|cookie| cookie.name() == STATE_COOKIE_NAME).unwrap();
+        assert_eq!(removal.value(), "");
+        assert_eq!(removal.path(), Some("/diosic/api/oidc"));
+        provider.stop().await;
+    }
+}
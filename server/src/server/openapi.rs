@@ -0,0 +1,189 @@
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use super::{api, oidc, share};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Diosic API"),
+    paths(
+        api::get_server_info,
+        api::setup,
+        api::get_media_url,
+        api::get_media_file,
+        api::get_media_cover,
+        api::get_media_info,
+        api::get_medias_archive,
+        api::get_medias,
+        api::get_sources,
+        api::create_user,
+        api::delete_user,
+        api::get_user,
+        api::get_users,
+        api::update_user,
+        api::update_user_role,
+        api::update_user_permissions,
+        api::get_user_libraries,
+        api::update_user_library,
+        api::get_roles,
+        api::save_role,
+        api::delete_role,
+        api::login_user,
+        api::get_auth_logs,
+        oidc::oidc_login,
+        oidc::oidc_callback,
+        api::logout_user,
+        api::get_current_user,
+        api::reload_medias,
+        api::reload_plugins,
+        api::get_plugins,
+        api::get_plugin,
+        api::update_plugin,
+        api::create_share,
+        api::get_shares,
+        api::delete_share,
+        api::create_api_key,
+        api::get_api_keys,
+        api::delete_api_key,
+        api::create_backup,
+        api::get_backups,
+        api::download_backup,
+    ),
+    modifiers(&SecurityAddon),
+    security((), ("token" = []), ("api_key" = []))
+)]
+struct Api;
+
+#[derive(OpenApi)]
+#[openapi(paths(share::get_share, share::get_share_media_file, share::get_share_media_cover, share::download_share_media_file))]
+struct PublicShare;
+
+/// Document of all routes, served at `/api/openapi.json`.
+#[derive(OpenApi)]
+#[openapi(nest((path = "/api", api = Api), (path = "/share", api = PublicShare)))]
+pub struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("token", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-authorization"))));
+        components.add_security_scheme("api_key", SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+
+    /// Source of every module in `server/`, a new module must be listed here to be checked.
+    const MODULES: [(&str, &str); 11] = [
+        ("api", include_str!("api.rs")),
+        ("dto", include_str!("dto.rs")),
+        ("error", include_str!("error.rs")),
+        ("from_requests", include_str!("from_requests.rs")),
+        ("health", include_str!("health.rs")),
+        ("metrics", include_str!("metrics.rs")),
+        ("oidc", include_str!("oidc.rs")),
+        ("openapi", include_str!("openapi.rs")),
+        ("share", include_str!("share.rs")),
+        ("tls", include_str!("tls.rs")),
+        ("webpage", include_str!("webpage.rs")),
+    ];
+    /// Served out of the base path for the probes and scrapers, they're not part of the API.
+    const UNDOCUMENTED: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+    /// Routes declared by the actix attributes in `source`, by the name of handler.
+    fn declared_routes(source: &str) -> HashMap<String, (String, String)> {
+        let mut routes = HashMap::new();
+        let mut attribute = None;
+        for line in source.lines().map(str::trim) {
+            if let Some((method, rest)) = line.strip_prefix("#[").and_then(|line| line.split_once("(\"")) {
+                if let Some((path, _)) = rest.split_once("\")]").filter(|_| ["get", "post", "put", "delete"].contains(&method)) {
+                    attribute = Some((method.to_owned(), path.to_owned()));
+                }
+            } else if let Some(name) = line.strip_prefix("pub async fn ").and_then(|line| line.split_once('(')) {
+                if let Some(route) = attribute.take() {
+                    routes.insert(name.0.to_owned(), route);
+                }
+            }
+        }
+        routes
+    }
+
+    /// Handlers registered by `.service(module::handler)` in `server.rs`, with the scope they're in.
+    fn registered_services() -> Vec<(String, String, String)> {
+        let mut prefix = String::new();
+        let mut services = vec![];
+        for line in include_str!("../server.rs").lines() {
+            if let Some((_, rest)) = line.split_once("web::scope(\"") {
+                prefix = rest.split_once('"').unwrap().0.to_owned();
+            }
+            for part in line.split(".service(").skip(1) {
+                let end = part.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':')).unwrap_or(part.len());
+                if !part[end..].starts_with([')', ',']) {
+                    continue;
+                }
+                if let Some((module, handler)) = part[..end].split_once("::") {
+                    services.push((prefix.clone(), module.to_owned(), handler.to_owned()));
+                }
+            }
+        }
+        services
+    }
+
+    #[test]
+    fn all_modules_are_listed() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/server");
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            assert!(MODULES.iter().any(|(module, _)| *module == name), "Unknown module `server/{name}`, list it in `MODULES`.");
+        }
+    }
+
+    #[test]
+    fn all_routes_are_documented() {
+        let doc = ApiDoc::openapi();
+        let modules: HashMap<_, _> = MODULES.iter().map(|(module, source)| (*module, declared_routes(source))).collect();
+        let services = registered_services();
+        assert!(!services.is_empty());
+
+        let mut registered = vec![];
+        for (prefix, module, handler) in &services {
+            let routes = modules.get(module.as_str()).unwrap_or_else(|| panic!("Unknown module `{module}` registered in `server.rs`."));
+            let (method, path) = routes.get(handler).unwrap_or_else(|| panic!("No found the route of `{module}::{handler}`."));
+            registered.push((module.clone(), handler.clone(), method.clone(), format!("{prefix}{path}")));
+        }
+        let unregistered: Vec<_> = modules
+            .iter()
+            .flat_map(|(module, routes)| routes.keys().map(move |handler| format!("{module}::{handler}")))
+            .filter(|name| !registered.iter().any(|(module, handler, ..)| *name == format!("{module}::{handler}")))
+            .collect();
+        assert!(unregistered.is_empty(), "Routes declared but not registered: {unregistered:?}");
+        for path in UNDOCUMENTED {
+            assert!(registered.iter().any(|route| route.3 == path), "Undocumented route `{path}` is not registered.");
+        }
+
+        let undocumented: Vec<_> = registered
+            .iter()
+            .filter(|(_, _, method, path)| {
+                let operation = doc.paths.paths.get(path).and_then(|item| match method.as_str() {
+                    "get" => item.get.as_ref(),
+                    "post" => item.post.as_ref(),
+                    "put" => item.put.as_ref(),
+                    _ => item.delete.as_ref(),
+                });
+                operation.is_none() && !UNDOCUMENTED.contains(&path.as_str())
+            })
+            .collect();
+        assert!(undocumented.is_empty(), "Routes without OpenAPI document: {undocumented:?}");
+    }
+}
@@ -0,0 +1,191 @@
+use actix_files::NamedFile;
+use actix_web::{
+    get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Json},
+    HttpRequest, HttpResponse, Responder,
+};
+
+use crate::{
+    library_system::model::{MediaInfo, Source},
+    server::dto::PubMediaInfo,
+    share_system::model::{PasswordFailure, ShareAccess, ShareInfo, ShareKind},
+    user_system::model::UserInfo,
+};
+
+use super::error::APIErrorType::*;
+use super::error::*;
+use super::{dto, AppState};
+
+type State = web::Data<AppState>;
+
+const SHARE_PASSWORD_HEADER: &str = "x-share-password";
+
+/// Get the share of token and its owner if it is alive and the access is granted.
+/// A protected share is granted by the password header or the signed access returned with the share.
+async fn verify_share(state: &State, token: &str, req: &HttpRequest, access: &ShareAccess) -> Result<(ShareInfo, UserInfo), APIError> {
+    let share = match state.share_system.get_share(token).await? {
+        Some(share) if !share.is_expired() => share,
+        Some(_) => return Err(APIError::with(NoFound).note("The share is expired.")),
+        None => return Err(APIError::with(NoFound).note("No found share with token!")),
+    };
+    // The share can't reach more than its owner, it is invalid once the owner is deleted.
+    if !state.user_system.exists_user(&share.owner).await? {
+        return Err(APIError::with(NoFound).note("No found share with token!"));
+    }
+    let owner = state.user_system.get_user(&share.owner).await?;
+    if state.share_system.verify_access(&share, access) {
+        return Ok((share, owner));
+    }
+    let password = req.headers().get(SHARE_PASSWORD_HEADER).and_then(|h| h.to_str().ok());
+    match state.share_system.verify_password(&share, password).await {
+        Ok(()) => Ok((share, owner)),
+        Err(PasswordFailure::Throttled(wait)) => Err(APIError::with(TooManyRequests).note(format!("Too many wrong passwords, please retry after {wait} seconds."))),
+        Err(PasswordFailure::Required) => Err(APIError::with(Unauthorized).note("The share requires password!")),
+        Err(PasswordFailure::Incorrect) => Err(APIError::with(Unauthorized).note("The share password is wrong!")),
+    }
+}
+
+async fn get_share_medias(state: &State, share: &ShareInfo, hidden_libraries: &[String]) -> Result<Vec<MediaInfo>, APIError> {
+    let source = match share.kind {
+        ShareKind::Media => {
+            let media = state.library_system.get_media_info_by_path(&share.target, hidden_libraries).await?;
+            return Ok(media.into_iter().collect());
+        }
+        ShareKind::Album => Source::Album(&share.target),
+        ShareKind::Category => Source::Category(&share.target),
+    };
+    let total = state.library_system.get_total_media(source, None, hidden_libraries).await?;
+    Ok(state.library_system.get_medias(source, None, 0, total, hidden_libraries).await?)
+}
+
+/// Get the media of id only if it belongs to the share.
+async fn get_share_media(state: &State, share: &ShareInfo, id: i64, hidden_libraries: &[String]) -> Result<MediaInfo, APIError> {
+    let media = state.library_system.get_media_info_by_id(id, hidden_libraries).await?;
+    let belongs = |media: &MediaInfo| match share.kind {
+        ShareKind::Media => share.target == media.path.to_string_lossy(),
+        ShareKind::Album => share.target == media.album,
+        ShareKind::Category => media.categories.contains(&share.target),
+    };
+    match media {
+        Some(media) if belongs(&media) => Ok(media),
+        _ => Err(APIError::with(NoFound).note("No found media in the share!")),
+    }
+}
+
+#[utoipa::path(
+    tag = "public_share",
+    params(ShareAccess, ("x-share-password" = Option<String>, Header, description = "Password of the protected share.")),
+    responses(
+        (status = 200, description = "Succeeded.", body = dto::ShareDetail),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/{token}")]
+pub async fn get_share(state: State, info: web::Path<(String,)>, req: HttpRequest, access: web::Query<ShareAccess>) -> Result<Json<dto::ShareDetail>, APIError> {
+    let (mut share, owner) = verify_share(&state, &info.0, &req, &access).await?;
+    let config = state.config.get();
+    let public_url = config.public_url.as_str();
+    // Media urls of a protected share carry the signed access, as players and images can't send the password header.
+    let access = share.password_required().then(|| state.share_system.sign_access(&share));
+    let medias: Vec<PubMediaInfo> = get_share_medias(&state, &share, &owner.hidden_libraries)
+        .await?
+        .into_iter()
+        .map(|media| {
+            let mut media: PubMediaInfo = media.into();
+            if media.cover_url.is_some() {
+                let mut cover_url = format!("{public_url}/share/{}/media_cover/{}", share.token, media.id);
+                if let Some(access) = &access {
+                    cover_url = format!("{cover_url}?{access}");
+                }
+                media.cover_url = Some(cover_url);
+            }
+            media
+        })
+        .collect();
+    // Don't expose the media path to the visitors.
+    if share.kind == ShareKind::Media {
+        share.target = medias.first().map(|media| media.id.to_string()).unwrap_or_default();
+    }
+    Ok(Json(dto::ShareDetail {
+        password_required: share.password_required(),
+        share,
+        access,
+        medias,
+    }))
+}
+
+#[utoipa::path(
+    tag = "public_share",
+    params(ShareAccess, ("x-share-password" = Option<String>, Header, description = "Password of the protected share.")),
+    responses(
+        (status = 200, description = "The media file, served inline for playing even if the share is not allowed to download, so that restriction is best-effort.", body = [u8], content_type = "application/octet-stream"),
+        (status = 206, description = "The requested range of media file.", body = [u8], content_type = "application/octet-stream"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/{token}/media_file/{id}")]
+pub async fn get_share_media_file(state: State, info: web::Path<(String, i64)>, req: HttpRequest, access: web::Query<ShareAccess>) -> Result<HttpResponse, APIError> {
+    let (share, owner) = verify_share(&state, &info.0, &req, &access).await?;
+    let media = get_share_media(&state, &share, info.1, &owner.hidden_libraries).await?;
+    let file = NamedFile::open_async(&media.path).await.map_err(|err| APIError::with(Unexpected).note(err.to_string()))?;
+    // Only the attachment is withheld if the share is not allowed to download, the players still need the whole stream.
+    let file = if share.allow_download {
+        file
+    } else {
+        file.set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Inline,
+            parameters: vec![],
+        })
+    };
+    Ok(file.respond_to(&req).map_into_boxed_body())
+}
+
+#[utoipa::path(
+    tag = "public_share",
+    params(ShareAccess, ("x-share-password" = Option<String>, Header, description = "Password of the protected share.")),
+    responses(
+        (status = 200, description = "The cover image.", body = [u8], content_type = "image/*"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/{token}/media_cover/{id}")]
+pub async fn get_share_media_cover(state: State, info: web::Path<(String, i64)>, req: HttpRequest, access: web::Query<ShareAccess>) -> Result<NamedFile, APIError> {
+    let (share, owner) = verify_share(&state, &info.0, &req, &access).await?;
+    let media = get_share_media(&state, &share, info.1, &owner.hidden_libraries).await?;
+    match media.cover_path {
+        Some(img) if img.is_file() => NamedFile::open_async(&img).await.map_err(|err| APIError::with(Unexpected).note(err.to_string())),
+        _ => Err(APIError::with(NoFound).note("The media cover is not exists.")),
+    }
+}
+
+#[utoipa::path(
+    tag = "public_share",
+    params(ShareAccess, ("x-share-password" = Option<String>, Header, description = "Password of the protected share.")),
+    responses(
+        (status = 200, description = "The media file as attachment.", body = [u8], content_type = "application/octet-stream"),
+        (status = "4XX", description = "The request is rejected.", body = APIErrorBody)
+    )
+)]
+#[get("/{token}/download/{id}")]
+pub async fn download_share_media_file(state: State, info: web::Path<(String, i64)>, req: HttpRequest, access: web::Query<ShareAccess>) -> Result<NamedFile, APIError> {
+    let (share, owner) = verify_share(&state, &info.0, &req, &access).await?;
+    if !share.allow_download {
+        return Err(APIError::with(NoPermission).note("The share is not allowed to download."));
+    }
+    let media = get_share_media(&state, &share, info.1, &owner.hidden_libraries).await?;
+    let file_name = media
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or(media.file_name);
+    NamedFile::open_async(&media.path)
+        .await
+        .map(|file| {
+            file.set_content_disposition(ContentDisposition {
+                disposition: DispositionType::Attachment,
+                parameters: vec![DispositionParam::Filename(file_name)],
+            })
+        })
+        .map_err(|err| APIError::with(Unexpected).note(err.to_string()))
+}
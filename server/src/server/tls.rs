@@ -0,0 +1,87 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::{self, CertifiedKey},
+    Certificate, PrivateKey, ServerConfig,
+};
+use rustls_pemfile::Item;
+use tracing::{error, info};
+
+use crate::config::TlsConfig;
+
+/// How often the modified time of certificate and key is checked.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Resolve the certificate loaded last, so it can be replaced without restart.
+pub struct CertResolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+fn load(tls: &TlsConfig) -> Result<CertifiedKey> {
+    let open = |path: &Path| File::open(path).map(BufReader::new).with_context(|| format!("Open `{}` failed!", path.display()));
+    let certs: Vec<_> = rustls_pemfile::certs(&mut open(&tls.cert)?)
+        .with_context(|| format!("Read the certificates of `{}` failed!", tls.cert.display()))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty() {
+        bail!("No certificate in `{}`.", tls.cert.display());
+    }
+    let mut key_reader = open(&tls.key)?;
+    let key = loop {
+        match rustls_pemfile::read_one(&mut key_reader).with_context(|| format!("Read the key of `{}` failed!", tls.key.display()))? {
+            Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key)) => break PrivateKey(key),
+            Some(_) => continue,
+            None => bail!("No private key in `{}`.", tls.key.display()),
+        }
+    };
+    let key = sign::any_supported_type(&key).map_err(|_| anyhow::anyhow!("The private key of `{}` is not supported.", tls.key.display()))?;
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Build the TLS config of server, the certificate is reloaded once the files are modified.
+pub fn server_config(tls: &TlsConfig) -> Result<ServerConfig> {
+    let resolver = Arc::new(CertResolver(RwLock::new(Arc::new(load(tls)?))));
+    tokio::spawn(watch(tls.clone(), resolver.clone()));
+    Ok(ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver))
+}
+
+fn modified_time(tls: &TlsConfig) -> [Option<SystemTime>; 2] {
+    [&tls.cert, &tls.key].map(|path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok())
+}
+
+async fn watch(tls: TlsConfig, resolver: Arc<CertResolver>) {
+    let mut modified = modified_time(&tls);
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = modified_time(&tls);
+        if current == modified {
+            continue;
+        }
+        modified = current;
+        // The certificate and key may be written one by one, the old ones are kept until both are valid.
+        match load(&tls) {
+            Ok(key) => {
+                *resolver.0.write().unwrap() = Arc::new(key);
+                info!("TLS certificate reloaded.");
+            }
+            Err(err) => error!("Reload the TLS certificate failed, the current one is kept! {err:#}"),
+        }
+    }
+}
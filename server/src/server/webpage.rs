@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use actix_files::NamedFile;
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        Method,
+    },
+    web, HttpRequest, HttpResponse,
+};
+use percent_encoding::percent_decode_str;
+
+use super::error::{APIError, APIErrorType};
+use crate::config::Config;
+
+/// Built by `npm run generate` of `web`.
+#[cfg(feature = "embed-webpage")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "$CARGO_MANIFEST_DIR/../web/.output/public"]
+struct Assets;
+
+/// Pages served for the routes of web UI, Nuxt generates `200.html` for the SPA.
+const FALLBACK_PAGES: [&str; 2] = ["200.html", "index.html"];
+/// Routes of server under the `base_path`, they are never served the web UI.
+const SERVER_ROUTES: [&str; 2] = ["api", "share"];
+
+enum Source {
+    Dir(PathBuf),
+    #[cfg(feature = "embed-webpage")]
+    Embedded,
+}
+
+/// Files of the web UI.
+pub struct Webpage {
+    source: Source,
+    base_path: String,
+}
+
+impl Webpage {
+    /// Serve the `webpage_dir` if it's settled, otherwise the embedded files or `./webpage` if they are not embedded.
+    pub fn new(config: &Config) -> Self {
+        let source = match &config.webpage_dir {
+            Some(dir) => Source::Dir(dir.clone()),
+            #[cfg(feature = "embed-webpage")]
+            None => Source::Embedded,
+            #[cfg(not(feature = "embed-webpage"))]
+            None => Source::Dir(PathBuf::from("webpage")),
+        };
+        Webpage {
+            source,
+            base_path: config.base_path.clone(),
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        match &self.source {
+            Source::Dir(dir) => dir.join(path).is_file(),
+            #[cfg(feature = "embed-webpage")]
+            Source::Embedded => Assets::get(path).is_some(),
+        }
+    }
+
+    async fn respond(&self, req: &HttpRequest, path: &str) -> Result<HttpResponse, APIError> {
+        let mut res = match &self.source {
+            Source::Dir(dir) => NamedFile::open_async(dir.join(path))
+                .await
+                .map_err(|err| APIError::with(APIErrorType::Unexpected).note(err.to_string()))?
+                .into_response(req),
+            #[cfg(feature = "embed-webpage")]
+            Source::Embedded => {
+                let file = Assets::get(path).ok_or_else(no_found)?;
+                let etag = format!("\"{}\"", hex::encode(file.metadata.sha256_hash()));
+                if req.headers().get(header::IF_NONE_MATCH).is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+                    HttpResponse::NotModified().insert_header((header::ETAG, etag)).finish()
+                } else {
+                    HttpResponse::Ok()
+                        .content_type(file.metadata.mimetype())
+                        .insert_header((header::ETAG, etag))
+                        .body(file.data.into_owned())
+                }
+            }
+        };
+        // The assets of Nuxt are named by their hash.
+        let cache_control = match path.starts_with("_nuxt/") {
+            true => "public, max-age=31536000, immutable",
+            false => "no-cache",
+        };
+        res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+        Ok(res)
+    }
+}
+
+fn no_found() -> APIError {
+    APIError::with(APIErrorType::NoFound).note("No found the page!")
+}
+
+/// Serve the file of web UI, the unknown routes without extension are served the page of SPA.
+/// Directories are never listed.
+pub async fn serve(req: HttpRequest, webpage: web::Data<Webpage>) -> Result<HttpResponse, APIError> {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return Err(no_found());
+    }
+    let path = req.path().strip_prefix(webpage.base_path.as_str()).unwrap_or(req.path());
+    let path = percent_decode_str(path).decode_utf8().map_err(|_| no_found())?;
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    // Hidden files and the parent directory are never reached.
+    if segments.iter().any(|segment| segment.starts_with('.') || segment.contains('\\')) {
+        return Err(no_found());
+    }
+    let relative = segments.join("/");
+
+    let candidates = match relative.is_empty() || path.ends_with('/') {
+        true => vec![format!("{relative}/index.html").trim_start_matches('/').to_owned()],
+        false => vec![relative.clone(), format!("{relative}/index.html")],
+    };
+    if let Some(file) = candidates.iter().find(|file| webpage.exists(file)) {
+        return webpage.respond(&req, file).await;
+    }
+
+    let is_asset = segments.last().is_some_and(|segment| segment.contains('.'));
+    let is_server_route = segments.first().is_some_and(|segment| SERVER_ROUTES.contains(segment));
+    if is_asset || is_server_route {
+        return Err(no_found());
+    }
+    match FALLBACK_PAGES.iter().find(|page| webpage.exists(page)) {
+        Some(page) => webpage.respond(&req, page).await,
+        None => Err(no_found()),
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use sqlx::{Pool, QueryBuilder, Row, Sqlite};
+
+use crate::{
+    db::DbResult,
+    user_system::{
+        model::UserInfo,
+        throttle::{Limit, LoginThrottle},
+        SIGNED_URL_EXPIRES_SECONDS,
+    },
+};
+
+use self::model::{PasswordFailure, ShareAccess, ShareInfo, ShareKind, ShareToCreate};
+
+pub mod model;
+
+/// Limit of failed password attempts for one share.
+const SHARE_LIMIT: Limit = Limit { free: 5, lockout: 20 };
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone)]
+pub struct ShareSystem {
+    db: Pool<Sqlite>,
+    access_secret: Arc<[u8; 32]>,
+    password_throttle: Arc<LoginThrottle>,
+}
+
+impl ShareSystem {
+    pub async fn new(db: Pool<Sqlite>) -> Result<Self> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS shares(
+                id INTEGER PRIMARY KEY,
+                token varchar(64) NOT NULL UNIQUE,
+                owner varchar(128) NOT NULL,
+                kind varchar(16) NOT NULL,
+                target TEXT NOT NULL,
+                password varchar(64) NULL,
+                allow_download BOOLEAN NOT NULL,
+                expires_at INTEGER NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS si_owner ON shares (owner);",
+        )
+        .execute(&db)
+        .await?;
+        Self::migrate_media_targets(&db).await?;
+        let mut access_secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut access_secret);
+        Ok(ShareSystem {
+            db,
+            access_secret: Arc::new(access_secret),
+            password_throttle: Arc::new(LoginThrottle::default()),
+        })
+    }
+
+    /// Media shares used to target the media id, which changes on every full scan, replace it with the media path.
+    async fn migrate_media_targets(db: &Pool<Sqlite>) -> Result<()> {
+        let medias_exists = sqlx::query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'medias'")
+            .fetch_optional(db)
+            .await?
+            .is_some();
+        if medias_exists {
+            sqlx::query(
+                "UPDATE shares SET target = medias.path FROM medias
+                WHERE shares.kind = ? AND shares.target NOT GLOB '*[^0-9]*' AND medias.id = CAST(shares.target AS INTEGER)",
+            )
+            .bind(ShareKind::Media)
+            .execute(db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Hash the password with a random salt, the result is a PHC string.
+    async fn hash_password(password: String) -> Result<String> {
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut OsRng);
+            Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|err| anyhow!("Hash the share password failed: {err}"))
+        })
+        .await?
+    }
+
+    /// `v.target` of media must be the media path.
+    pub async fn create_share(&self, owner: &UserInfo, v: ShareToCreate) -> Result<ShareInfo> {
+        let mut token = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut token);
+        let password = match v.password {
+            Some(password) => Some(Self::hash_password(password).await?),
+            None => None,
+        };
+        let share = sqlx::query_as::<_, ShareInfo>(
+            "INSERT INTO shares (token, owner, kind, target, password, allow_download, expires_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING *",
+        )
+        .bind(hex::encode(token))
+        .bind(&owner.username)
+        .bind(v.kind)
+        .bind(&v.target)
+        .bind(password)
+        .bind(v.allow_download)
+        .bind(v.expires_at)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(share)
+    }
+
+    pub async fn get_share(&self, token: &str) -> DbResult<Option<ShareInfo>> {
+        Ok(sqlx::query_as::<_, ShareInfo>("SELECT * FROM shares WHERE token = ? LIMIT 1")
+            .bind(token)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    fn get_shares_core_query(&self, main: &str, owner: Option<&str>) -> QueryBuilder<'_, Sqlite> {
+        let mut builder = QueryBuilder::new(main);
+        if let Some(owner) = owner {
+            builder.push(" WHERE owner = ").push_bind(owner.to_owned());
+        }
+        builder
+    }
+
+    /// Get shares of all users if `owner` is `None`.
+    pub async fn get_shares(&self, index: usize, limit: usize, owner: Option<&str>) -> DbResult<Vec<ShareInfo>> {
+        Ok(self
+            .get_shares_core_query("SELECT * FROM shares", owner)
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(limit as i64)
+            .push(" OFFSET ")
+            .push_bind((index * limit) as i64)
+            .build_query_as()
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    pub async fn get_total_share(&self, owner: Option<&str>) -> DbResult<usize> {
+        let row = self
+            .get_shares_core_query("SELECT COUNT(1) AS count FROM shares", owner)
+            .build()
+            .fetch_one(&self.db)
+            .await?;
+        Ok(row.try_get::<u32, _>("count")? as usize)
+    }
+
+    pub async fn delete_share(&self, token: &str) -> Result<bool> {
+        let r = sqlx::query("DELETE FROM shares WHERE token = ?")
+            .bind(token)
+            .execute(&self.db)
+            .await?;
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Verify the password of a protected share, failed attempts of a share are throttled.
+    pub async fn verify_password(&self, share: &ShareInfo, password: Option<&str>) -> Result<(), PasswordFailure> {
+        let Some(hashed) = share.password.clone() else {
+            return Ok(());
+        };
+        let key = format!("share:{}", share.token);
+        if let Some(wait) = self.password_throttle.retry_after(&[&key]).await {
+            return Err(PasswordFailure::Throttled(wait));
+        }
+        let Some(password) = password.map(str::to_owned) else {
+            return Err(PasswordFailure::Required);
+        };
+        // The verifier compares the hashes in constant time.
+        let matched = tokio::task::spawn_blocking(move || {
+            PasswordHash::new(&hashed).is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        })
+        .await
+        .unwrap_or(false);
+        if matched {
+            self.password_throttle.succeeded(&key).await;
+            Ok(())
+        } else {
+            self.password_throttle.failed(&key, SHARE_LIMIT).await;
+            Err(PasswordFailure::Incorrect)
+        }
+    }
+
+    fn access_mac(&self, token: &str, expires: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.access_secret.as_slice()).expect("HMAC can take key of any size");
+        mac.update(format!("share:{token}:{expires}").as_bytes());
+        mac
+    }
+
+    /// Sign the access of a share whose password is verified, return the url query string.
+    pub fn sign_access(&self, share: &ShareInfo) -> String {
+        let expires = chrono::Utc::now().timestamp() + SIGNED_URL_EXPIRES_SECONDS;
+        let signature = hex::encode(self.access_mac(&share.token, expires).finalize().into_bytes());
+        format!("expires={expires}&signature={signature}")
+    }
+
+    pub fn verify_access(&self, share: &ShareInfo, access: &ShareAccess) -> bool {
+        let (Some(expires), Some(signature)) = (access.expires, access.signature.as_deref()) else {
+            return false;
+        };
+        expires >= chrono::Utc::now().timestamp()
+            && hex::decode(signature).is_ok_and(|signature| self.access_mac(&share.token, expires).verify_slice(&signature).is_ok())
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum ShareKind {
+    Media,
+    Album,
+    Category,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ShareToCreate {
+    pub kind: ShareKind,
+    /// Media id for `media`, album title for `album` and category title for `category`.
+    /// The media id is stored as the media path, which is stable across scans.
+    pub target: String,
+    pub expires_at: Option<i64>,
+    pub password: Option<String>,
+    /// Offer the media files as attachments. It's best-effort if disabled: the files are still streamed
+    /// to the players, so the visitors can save the stream anyway.
+    #[serde(default)]
+    pub allow_download: bool,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct ShareInfo {
+    pub id: i64,
+    pub token: String,
+    pub owner: String,
+    pub kind: ShareKind,
+    pub target: String,
+    #[serde(skip)]
+    pub password: Option<String>,
+    /// Offer the media files as attachments. It's best-effort if disabled: the files are still streamed
+    /// to the players, so the visitors can save the stream anyway.
+    pub allow_download: bool,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+}
+
+impl ShareInfo {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at < chrono::Utc::now().timestamp())
+    }
+
+    pub fn password_required(&self) -> bool {
+        self.password.is_some()
+    }
+}
+
+#[derive(Debug)]
+pub enum PasswordFailure {
+    /// Too many failed attempts, must wait these seconds.
+    Throttled(i64),
+    Required,
+    Incorrect,
+}
+
+/// Signed access of a protected share, issued once the password is verified.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShareAccess {
+    pub expires: Option<i64>,
+    pub signature: Option<String>,
+}
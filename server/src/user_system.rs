@@ -1,17 +1,58 @@
-use crate::myutil;
+use crate::{
+    db::{self, DbError, DbResult},
+    myutil,
+};
 use anyhow::Result;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use sqlx::{Pool, QueryBuilder, Row, Sqlite};
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::RwLock;
+use tracing::error;
 
-use self::model::{UserInfo, UserToCreate};
+use self::throttle::{LoginThrottle, ACCOUNT_LIMIT, IP_LIMIT, LOCKOUT_SECONDS};
+
+use self::model::{
+    ApiKeyCreated, ApiKeyInfo, ApiKeyToCreate, AuthLogInfo, Capability, LoginFailure, OidcIdentity, MediaSignature, RoleInfo, SignedMedia, UserInfo, UserPermissionsToUpdate, UserToCreate,
+    ADMIN_ROLE, GUEST_ROLE, USER_ROLE,
+};
 
 pub mod model;
+pub mod oidc;
+pub mod throttle;
+
+/// Prefix of api keys, make them easy to recognize.
+pub const API_KEY_PREFIX: &str = "dsk_";
+
+/// How long a signed media url stay valid.
+pub const SIGNED_URL_EXPIRES_SECONDS: i64 = 6 * 60 * 60;
+
+/// The auth logs older than it are deleted.
+pub const AUTH_LOG_RETENTION_SECONDS: i64 = 90 * 24 * 60 * 60;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Select users with the capabilities of their role and the libraries hidden from them.
+const USERS_SELECT: &str = "SELECT users.*, COALESCE(roles.capabilities, '[]') AS capabilities,
+    (SELECT json_group_array(library) FROM hidden_libraries WHERE user_id = users.id) AS hidden_libraries
+    FROM users LEFT JOIN roles ON roles.name = users.role";
+
+/// Delete the auth logs out of [`AUTH_LOG_RETENTION_SECONDS`], it's cheap by the index of `created_at`.
+async fn prune_auth_logs(db: &Pool<Sqlite>) -> DbResult<()> {
+    sqlx::query("DELETE FROM auth_log WHERE created_at < ?")
+        .bind(chrono::Utc::now().timestamp() - AUTH_LOG_RETENTION_SECONDS)
+        .execute(db)
+        .await?;
+    Ok(())
+}
 
 #[derive(Debug, Clone)]
 pub struct UserSystem {
     db: Pool<Sqlite>,
     user_tokens: Arc<RwLock<HashMap<String, UserInfo>>>,
+    url_secret: Arc<[u8; 32]>,
+    login_throttle: Arc<LoginThrottle>,
 }
 
 impl UserSystem {
@@ -22,87 +63,256 @@ impl UserSystem {
                 username varchar(128) NOT NULL,
                 password varchar(128) NOT NULL,
                 alias varchar(128) NOT NULL,
-                is_admin BOOLEAN NOT NULL
+                is_admin BOOLEAN NOT NULL,
+                can_download BOOLEAN NOT NULL DEFAULT 1,
+                role varchar(64) NOT NULL DEFAULT 'user'
             );
             CREATE INDEX IF NOT EXISTS ui_username ON users (username);
             CREATE INDEX IF NOT EXISTS ui_password ON users (password);
             CREATE INDEX IF NOT EXISTS ui_alias ON users (alias);
-            CREATE INDEX IF NOT EXISTS ui_is_admin ON users (is_admin);",
+            CREATE INDEX IF NOT EXISTS ui_is_admin ON users (is_admin);
+            CREATE TABLE IF NOT EXISTS roles(
+                name varchar(64) PRIMARY KEY,
+                capabilities TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS hidden_libraries(
+                user_id INTEGER NOT NULL,
+                library TEXT NOT NULL,
+                PRIMARY KEY (user_id, library)
+            );
+            CREATE TABLE IF NOT EXISTS api_keys(
+                id INTEGER PRIMARY KEY,
+                user_id INTEGER NOT NULL,
+                name varchar(128) NOT NULL,
+                key_hash varchar(64) NOT NULL UNIQUE,
+                scopes TEXT NOT NULL,
+                expires_at INTEGER NULL,
+                created_at INTEGER NOT NULL,
+                last_used_at INTEGER NULL
+            );
+            CREATE INDEX IF NOT EXISTS aki_user_id ON api_keys (user_id);
+            CREATE TABLE IF NOT EXISTS auth_log(
+                id INTEGER PRIMARY KEY,
+                username varchar(128) NOT NULL,
+                ip varchar(64) NOT NULL,
+                success BOOLEAN NOT NULL,
+                note TEXT NULL,
+                attempts INTEGER NOT NULL DEFAULT 1,
+                created_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS ali_username ON auth_log (username);
+            CREATE INDEX IF NOT EXISTS ali_created_at ON auth_log (created_at);",
         )
         .execute(&db)
-        .await
-        .unwrap();
+        .await?;
+        if db::add_column_if_missing(&db, "users", "role", "varchar(64) NOT NULL DEFAULT 'user'").await? {
+            // Users from older version are assigned by the old rules.
+            sqlx::query(
+                "UPDATE users SET role='admin' WHERE is_admin=1;
+                UPDATE users SET role='guest', is_admin=0 WHERE username='guest';",
+            )
+            .execute(&db)
+            .await?;
+        }
+        db::add_column_if_missing(&db, "users", "can_download", "BOOLEAN NOT NULL DEFAULT 1").await?;
+        db::add_column_if_missing(&db, "users", "oidc_subject", "TEXT NULL").await?;
+        db::add_column_if_missing(&db, "auth_log", "attempts", "INTEGER NOT NULL DEFAULT 1").await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS ui_role ON users (role); CREATE INDEX IF NOT EXISTS ui_oidc_subject ON users (oidc_subject);")
+            .execute(&db)
+            .await?;
+        let builtin_roles = [
+            (ADMIN_ROLE, Capability::ALL.to_vec()),
+            (USER_ROLE, vec![Capability::Stream, Capability::Download, Capability::ManagePlaylists, Capability::Share]),
+            (GUEST_ROLE, vec![Capability::Stream]),
+        ];
+        for (name, capabilities) in builtin_roles {
+            sqlx::query("INSERT OR IGNORE INTO roles (name, capabilities) VALUES (?, ?)")
+                .bind(name)
+                .bind(serde_json::to_string(&capabilities)?)
+                .execute(&db)
+                .await?;
+        }
+        let mut url_secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut url_secret);
+        prune_auth_logs(&db).await?;
         Ok(UserSystem {
             db,
             user_tokens: Arc::new(RwLock::new(HashMap::new())),
+            url_secret: Arc::new(url_secret),
+            login_throttle: Arc::new(LoginThrottle::default()),
         })
     }
 
-    pub async fn is_guest_enabled(&self) -> bool {
-        sqlx::query("SELECT id FROM users WHERE username='guest' LIMIT 1")
+    pub async fn is_guest_enabled(&self) -> DbResult<bool> {
+        Ok(sqlx::query("SELECT id FROM users WHERE username='guest' LIMIT 1")
             .fetch_optional(&self.db)
-            .await
-            .expect("Check guest enabled failed!")
-            .is_some()
+            .await?
+            .is_some())
     }
 
-    pub async fn is_guest_password_required(&self) -> bool {
+    pub async fn is_guest_password_required(&self) -> DbResult<bool> {
         if let Some(row) = sqlx::query("SELECT password FROM users WHERE username='guest' LIMIT 1")
             .fetch_optional(&self.db)
-            .await
-            .expect("Check guest enabled failed!")
+            .await?
         {
-            let password: String = row.get("password");
-            password != ""
+            let password: String = row.try_get("password")?;
+            Ok(!password.is_empty())
         } else {
-            false
+            Ok(false)
         }
     }
 
-    pub async fn create_user(&self, v: UserToCreate, is_admin: bool) -> Result<()> {
-        sqlx::query("INSERT INTO users (username, alias, password, is_admin) VALUES (?, ?, ?, ?)")
+    pub async fn create_user(&self, v: UserToCreate, role: &str) -> DbResult<()> {
+        sqlx::query("INSERT INTO users (username, alias, password, is_admin, role) VALUES (?, ?, ?, ?, ?)")
             .bind(&v.username)
             .bind(&v.alias)
             .bind(&v.password)
-            .bind(is_admin)
+            .bind(role == ADMIN_ROLE)
+            .bind(role)
             .execute(&self.db)
             .await?;
         Ok(())
     }
 
-    pub async fn delete_user(&self, user: &UserInfo) -> Result<bool> {
+    pub async fn delete_user(&self, user: &UserInfo) -> DbResult<bool> {
         let r = sqlx::query("DELETE FROM users WHERE username=?")
             .bind(&user.username)
             .execute(&self.db)
             .await?;
-        let token = self.get_user_token(&user);
+        sqlx::query("DELETE FROM hidden_libraries WHERE user_id=?; DELETE FROM api_keys WHERE user_id=?;")
+            .bind(user.id)
+            .bind(user.id)
+            .execute(&self.db)
+            .await?;
+        let token = self.get_user_token(user);
         self.logout(&token).await;
         Ok(r.rows_affected() > 0)
     }
 
-    pub async fn update_user(&self, user_old: &UserInfo, user_update: UserToCreate) -> bool {
+    pub async fn update_user(&self, user_old: &UserInfo, user_update: UserToCreate) -> DbResult<bool> {
         let r = sqlx::query("UPDATE users SET username=?, alias=?, password=? WHERE username=?")
             .bind(&user_update.username)
             .bind(&user_update.alias)
             .bind(&user_update.password)
             .bind(&user_old.username)
             .execute(&self.db)
-            .await
-            .expect("Update user failed!");
-        let token = self.get_user_token(&user_old);
+            .await?;
+        let token = self.get_user_token(user_old);
         self.logout(&token).await;
-        r.rows_affected() > 0
+        Ok(r.rows_affected() > 0)
     }
 
-    pub async fn get_user(&self, username: &str) -> UserInfo {
-        sqlx::query_as::<_, UserInfo>("SELECT * FROM users WHERE username = ? LIMIT 1")
+    /// `is_admin` follows the role, so the admin role is the only way to be admin.
+    pub async fn update_user_role(&self, username: &str, role: &str) -> DbResult<bool> {
+        let r = sqlx::query("UPDATE users SET role=?, is_admin=? WHERE username=?")
+            .bind(role)
+            .bind(role == ADMIN_ROLE)
             .bind(username)
-            .fetch_one(&self.db)
-            .await
-            .expect("Get User failed!")
+            .execute(&self.db)
+            .await?;
+        self.refresh_sessions().await?;
+        Ok(r.rows_affected() > 0)
+    }
+
+    pub async fn update_user_permissions(&self, username: &str, to_update: &UserPermissionsToUpdate) -> DbResult<bool> {
+        let r = sqlx::query("UPDATE users SET can_download=? WHERE username=?")
+            .bind(to_update.can_download)
+            .bind(username)
+            .execute(&self.db)
+            .await?;
+        self.refresh_sessions().await?;
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Grant or deny the access of library for the user.
+    pub async fn set_library_access(&self, user: &UserInfo, library: &str, allowed: bool) -> DbResult<()> {
+        let query = if allowed {
+            "DELETE FROM hidden_libraries WHERE user_id = ? AND library = ?"
+        } else {
+            "INSERT OR IGNORE INTO hidden_libraries (user_id, library) VALUES (?, ?)"
+        };
+        sqlx::query(query)
+            .bind(user.id)
+            .bind(library)
+            .execute(&self.db)
+            .await?;
+        self.refresh_sessions().await?;
+        Ok(())
+    }
+
+    pub async fn get_roles(&self) -> DbResult<Vec<RoleInfo>> {
+        Ok(sqlx::query_as::<_, RoleInfo>("SELECT * FROM roles ORDER BY name")
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    pub async fn get_role(&self, name: &str) -> DbResult<Option<RoleInfo>> {
+        Ok(sqlx::query_as::<_, RoleInfo>("SELECT * FROM roles WHERE name = ? LIMIT 1")
+            .bind(name)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    pub async fn exists_role(&self, name: &str) -> DbResult<bool> {
+        Ok(sqlx::query("SELECT name FROM roles WHERE name = ? LIMIT 1")
+            .bind(name)
+            .fetch_optional(&self.db)
+            .await?
+            .is_some())
+    }
+
+    /// Create the role or replace the capabilities of existing role.
+    pub async fn save_role(&self, role: &RoleInfo) -> DbResult<()> {
+        sqlx::query("INSERT OR REPLACE INTO roles (name, capabilities) VALUES (?, ?)")
+            .bind(&role.name)
+            .bind(serde_json::to_string(&role.capabilities)?)
+            .execute(&self.db)
+            .await?;
+        self.refresh_sessions().await?;
+        Ok(())
+    }
+
+    pub async fn delete_role(&self, name: &str) -> DbResult<bool> {
+        let r = sqlx::query("DELETE FROM roles WHERE name = ?")
+            .bind(name)
+            .execute(&self.db)
+            .await?;
+        sqlx::query("UPDATE users SET role=? WHERE role=?")
+            .bind(USER_ROLE)
+            .bind(name)
+            .execute(&self.db)
+            .await?;
+        self.refresh_sessions().await?;
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Logged in sessions should see the changes of role immediately.
+    async fn refresh_sessions(&self) -> DbResult<()> {
+        let mut tokens = self.user_tokens.write().await;
+        for user in tokens.values_mut() {
+            if let Some(fresh) = self.get_user_by_id(user.id).await? {
+                *user = fresh;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_user(&self, username: &str) -> DbResult<UserInfo> {
+        sqlx::query_as::<_, UserInfo>(&format!("{USERS_SELECT} WHERE username = ? LIMIT 1"))
+            .bind(username)
+            .fetch_optional(&self.db)
+            .await?
+            .ok_or(DbError::NotFound("user"))
     }
 
-    fn get_users_core_query(&self, main: &str, to_search: Option<&str>) -> QueryBuilder<Sqlite> {
+    pub async fn get_user_by_id(&self, id: i64) -> DbResult<Option<UserInfo>> {
+        Ok(sqlx::query_as::<_, UserInfo>(&format!("{USERS_SELECT} WHERE users.id = ? LIMIT 1"))
+            .bind(id)
+            .fetch_optional(&self.db)
+            .await?)
+    }
+
+    fn get_users_core_query(&self, main: &str, to_search: Option<&str>) -> QueryBuilder<'_, Sqlite> {
         let mut builder = QueryBuilder::new(main);
         if let Some(s) = to_search {
             let s = format!("%{s}%");
@@ -116,43 +326,40 @@ impl UserSystem {
         index: usize,
         limit: usize,
         to_search: Option<&str>,
-    ) -> Vec<UserInfo> {
-        self.get_users_core_query("SELECT * FROM users", to_search)
+    ) -> DbResult<Vec<UserInfo>> {
+        Ok(self
+            .get_users_core_query(USERS_SELECT, to_search)
             .push(" LIMIT ")
             .push_bind(limit as i64)
             .push(" OFFSET ")
             .push_bind((index * limit) as i64)
             .build_query_as()
             .fetch_all(&self.db)
-            .await
-            .expect("Get all users failed!")
+            .await?)
     }
 
-    pub async fn get_total_user(&self, to_search: Option<&str>) -> usize {
-        self.get_users_core_query("SELECT COUNT(1) AS count FROM users", to_search)
+    pub async fn get_total_user(&self, to_search: Option<&str>) -> DbResult<usize> {
+        let row = self
+            .get_users_core_query("SELECT COUNT(1) AS count FROM users", to_search)
             .build()
-            .fetch_optional(&self.db)
-            .await
-            .expect("Get total media failed!")
-            .map(|row| row.get::<u32, _>("count") as usize)
-            .unwrap_or(0)
+            .fetch_one(&self.db)
+            .await?;
+        Ok(row.try_get::<u32, _>("count")? as usize)
     }
 
-    pub async fn exists_user(&self, username: &str) -> bool {
-        sqlx::query("SELECT id FROM users WHERE username = ? LIMIT 1")
+    pub async fn exists_user(&self, username: &str) -> DbResult<bool> {
+        Ok(sqlx::query("SELECT id FROM users WHERE username = ? LIMIT 1")
             .bind(username)
             .fetch_optional(&self.db)
-            .await
-            .expect("Check exists user failed!")
-            .is_some()
+            .await?
+            .is_some())
     }
 
-    pub async fn contains_users(&self) -> bool {
-        sqlx::query("SELECT id FROM users LIMIT 1")
+    pub async fn contains_users(&self) -> DbResult<bool> {
+        Ok(sqlx::query("SELECT id FROM users LIMIT 1")
             .fetch_optional(&self.db)
-            .await
-            .expect("Check contains users failed!")
-            .is_some()
+            .await?
+            .is_some())
     }
 
     pub fn get_user_token(&self, user: &UserInfo) -> String {
@@ -160,28 +367,185 @@ impl UserSystem {
         myutil::calc_hash(&ident).to_string()
     }
 
-    pub async fn login(&self, username: &str, password: &str) -> Option<String> {
-        if self.exists_user(username).await {
-            let user = self.get_user(username).await;
-            if user.password == password {
+    /// Login from the `ip`, failed attempts are throttled by account and ip, and all attempts are logged.
+    pub async fn login(&self, username: &str, password: &str, ip: &str) -> Result<String, LoginFailure> {
+        let account_key = format!("user:{username}");
+        let ip_key = format!("ip:{ip}");
+        if let Some(wait) = self.login_throttle.retry_after(&[&account_key, &ip_key]).await {
+            self.record_throttled(username, ip).await?;
+            return Err(LoginFailure::Throttled(wait));
+        }
+
+        let user = match self.get_user(username).await {
+            Ok(user) => Some(user),
+            Err(DbError::NotFound(_)) => None,
+            Err(err) => return Err(err.into()),
+        };
+        match user {
+            Some(user) if user.password == password => {
+                self.login_throttle.succeeded(&account_key).await;
+                self.record_auth(username, ip, true, None).await?;
                 let token = self.get_user_token(&user);
                 self.user_tokens
                     .write()
                     .await
                     .insert(token.clone(), user.clone());
-                Some(token)
-            } else {
-                None
+                Ok(token)
             }
-        } else {
-            None
+            user => {
+                self.login_throttle.failed(&account_key, ACCOUNT_LIMIT).await;
+                self.login_throttle.failed(&ip_key, IP_LIMIT).await;
+                let note = if user.is_some() { "wrong password" } else { "unknown user" };
+                self.record_auth(username, ip, false, Some(note)).await?;
+                Err(LoginFailure::Incorrect)
+            }
+        }
+    }
+
+    /// Login the user authenticated by OpenID Connect, provision the user at the first time.
+    /// The role of provisioned user is synchronized with the provider every login.
+    pub async fn login_oidc(&self, identity: &OidcIdentity, ip: &str) -> Result<String> {
+        let id = sqlx::query("SELECT id FROM users WHERE oidc_subject = ? LIMIT 1")
+            .bind(&identity.subject)
+            .fetch_optional(&self.db)
+            .await?
+            .map(|row| row.try_get::<i64, _>("id"))
+            .transpose()?;
+        let id = match id {
+            Some(id) => {
+                sqlx::query("UPDATE users SET role = ?, is_admin = ? WHERE id = ?")
+                    .bind(identity.role)
+                    .bind(identity.role == ADMIN_ROLE)
+                    .bind(id)
+                    .execute(&self.db)
+                    .await?;
+                self.refresh_sessions().await?;
+                id
+            }
+            None => {
+                if self.exists_user(&identity.username).await? {
+                    anyhow::bail!("Username `{}` is already used by another user.", identity.username);
+                }
+                // Provisioned user only can login by the provider.
+                let mut password = [0u8; 32];
+                rand::thread_rng().fill_bytes(&mut password);
+                let to_create = UserToCreate {
+                    alias: identity.alias.clone(),
+                    username: identity.username.clone(),
+                    password: hex::encode(password),
+                };
+                self.create_user(to_create, identity.role).await?;
+                sqlx::query("UPDATE users SET oidc_subject = ? WHERE username = ? RETURNING id")
+                    .bind(&identity.subject)
+                    .bind(&identity.username)
+                    .fetch_one(&self.db)
+                    .await?
+                    .try_get::<i64, _>("id")?
+            }
+        };
+        let user = self.get_user_by_id(id).await?.ok_or(DbError::NotFound("user"))?;
+        self.record_auth(&user.username, ip, true, Some("oidc")).await?;
+        let token = self.get_user_token(&user);
+        self.user_tokens.write().await.insert(token.clone(), user);
+        Ok(token)
+    }
+
+    /// Get the user authenticated by the reverse proxy, create it at first sight if `create_role` is settled.
+    pub async fn get_proxy_user(&self, username: &str, create_role: Option<&str>) -> DbResult<Option<UserInfo>> {
+        if !self.exists_user(username).await? {
+            let Some(role) = create_role else {
+                return Ok(None);
+            };
+            // The role may be deleted or the config reloaded after startup.
+            if !self.exists_role(role).await? {
+                error!("The `proxy_auth.create_role` `{role}` is not exists, user `{username}` is rejected.");
+                return Ok(None);
+            }
+            // Created user only can login by the reverse proxy.
+            let mut password = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut password);
+            // Concurrent requests of the new user must not create it twice.
+            sqlx::query(
+                "INSERT INTO users (username, alias, password, is_admin, role)
+                SELECT ?1, ?1, ?2, ?3, ?4 WHERE NOT EXISTS (SELECT 1 FROM users WHERE username = ?1)",
+            )
+            .bind(username)
+            .bind(hex::encode(password))
+            .bind(role == ADMIN_ROLE)
+            .bind(role)
+            .execute(&self.db)
+            .await?;
+        }
+        self.get_user(username).await.map(Some)
+    }
+
+    pub async fn record_auth(&self, username: &str, ip: &str, success: bool, note: Option<&str>) -> DbResult<()> {
+        sqlx::query("INSERT INTO auth_log (username, ip, success, note, created_at) VALUES (?, ?, ?, ?, ?)")
+            .bind(username)
+            .bind(ip)
+            .bind(success)
+            .bind(note)
+            .bind(chrono::Utc::now().timestamp())
+            .execute(&self.db)
+            .await?;
+        prune_auth_logs(&self.db).await?;
+        Ok(())
+    }
+
+    /// Count the throttled attempt in the entry of its lockout window, so retrying in lockout doesn't grow the logs.
+    async fn record_throttled(&self, username: &str, ip: &str) -> DbResult<()> {
+        let now = chrono::Utc::now().timestamp();
+        let counted = sqlx::query(
+            "UPDATE auth_log SET attempts = attempts + 1 WHERE id = (
+                SELECT id FROM auth_log WHERE username = ? AND ip = ? AND note = 'throttled' AND created_at > ?
+                ORDER BY id DESC LIMIT 1
+            )",
+        )
+        .bind(username)
+        .bind(ip)
+        .bind(now - LOCKOUT_SECONDS)
+        .execute(&self.db)
+        .await?
+        .rows_affected();
+        if counted == 0 {
+            self.record_auth(username, ip, false, Some("throttled")).await?;
+        }
+        Ok(())
+    }
+
+    fn get_auth_logs_core_query<'a>(&self, init: &'a str, username: Option<&'a str>) -> QueryBuilder<'a, Sqlite> {
+        let mut qb = QueryBuilder::new(init);
+        if let Some(username) = username {
+            qb.push(" WHERE username = ").push_bind(username);
         }
+        qb
+    }
+
+    pub async fn get_auth_logs(&self, index: usize, limit: usize, username: Option<&str>) -> DbResult<Vec<AuthLogInfo>> {
+        Ok(self
+            .get_auth_logs_core_query("SELECT * FROM auth_log", username)
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(limit as i64)
+            .push(" OFFSET ")
+            .push_bind((index * limit) as i64)
+            .build_query_as()
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    pub async fn get_total_auth_log(&self, username: Option<&str>) -> DbResult<usize> {
+        let row = self
+            .get_auth_logs_core_query("SELECT COUNT(1) AS count FROM auth_log", username)
+            .build()
+            .fetch_one(&self.db)
+            .await?;
+        Ok(row.try_get::<u32, _>("count")? as usize)
     }
 
     pub async fn logout(&self, token: &str) -> bool {
         match self.verify(token).await {
             Some(user) => {
-                if user.username != "guest" {
+                if !user.is_guest() {
                     self.user_tokens.write().await.remove(token);
                 }
                 true
@@ -191,6 +555,141 @@ impl UserSystem {
     }
 
     pub async fn verify(&self, token: &str) -> Option<UserInfo> {
-        self.user_tokens.read().await.get(token).map(|u| u.clone())
+        self.user_tokens.read().await.get(token).cloned()
+    }
+
+    fn media_mac(&self, kind: SignedMedia, id: i64, uid: i64, expires: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.url_secret.as_slice()).expect("HMAC can take key of any size");
+        mac.update(format!("{}:{id}:{uid}:{expires}", kind.as_str()).as_bytes());
+        mac
+    }
+
+    /// Sign the media of `kind` and `id` for the user, return the url query string.
+    pub fn sign_media(&self, kind: SignedMedia, id: i64, user: &UserInfo) -> String {
+        let expires = chrono::Utc::now().timestamp() + SIGNED_URL_EXPIRES_SECONDS;
+        let signature = hex::encode(self.media_mac(kind, id, user.id, expires).finalize().into_bytes());
+        format!("uid={}&expires={expires}&signature={signature}", user.id)
+    }
+
+    /// Return the owner of signature if it is valid and not expired.
+    pub async fn verify_media_signature(&self, kind: SignedMedia, id: i64, sig: &MediaSignature) -> DbResult<Option<UserInfo>> {
+        if sig.expires < chrono::Utc::now().timestamp() {
+            return Ok(None);
+        }
+        let verified = hex::decode(&sig.signature)
+            .is_ok_and(|signature| self.media_mac(kind, id, sig.uid, sig.expires).verify_slice(&signature).is_ok());
+        if !verified {
+            return Ok(None);
+        }
+        self.get_user_by_id(sig.uid).await
+    }
+
+    fn hash_api_key(key: &str) -> String {
+        hex::encode(Sha256::digest(key.as_bytes()))
+    }
+
+    pub async fn create_api_key(&self, user: &UserInfo, v: ApiKeyToCreate) -> DbResult<ApiKeyCreated> {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let key = format!("{API_KEY_PREFIX}{}", hex::encode(secret));
+        let info = sqlx::query_as::<_, ApiKeyInfo>(
+            "INSERT INTO api_keys (user_id, name, key_hash, scopes, expires_at, created_at)
+            VALUES (?, ?, ?, ?, ?, ?) RETURNING *",
+        )
+        .bind(user.id)
+        .bind(&v.name)
+        .bind(Self::hash_api_key(&key))
+        .bind(serde_json::to_string(&v.scopes)?)
+        .bind(v.expires_at)
+        .bind(chrono::Utc::now().timestamp())
+        .fetch_one(&self.db)
+        .await?;
+        Ok(ApiKeyCreated { info, key })
+    }
+
+    pub async fn get_api_keys(&self, user: &UserInfo) -> DbResult<Vec<ApiKeyInfo>> {
+        Ok(sqlx::query_as::<_, ApiKeyInfo>("SELECT * FROM api_keys WHERE user_id = ? ORDER BY id")
+            .bind(user.id)
+            .fetch_all(&self.db)
+            .await?)
+    }
+
+    pub async fn delete_api_key(&self, user: &UserInfo, id: i64) -> DbResult<bool> {
+        let r = sqlx::query("DELETE FROM api_keys WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user.id)
+            .execute(&self.db)
+            .await?;
+        Ok(r.rows_affected() > 0)
+    }
+
+    /// Return the owner of key limited by the scopes of key, and record the key is used.
+    pub async fn verify_api_key(&self, key: &str) -> DbResult<Option<UserInfo>> {
+        let info = sqlx::query_as::<_, ApiKeyInfo>("SELECT * FROM api_keys WHERE key_hash = ? LIMIT 1")
+            .bind(Self::hash_api_key(key))
+            .fetch_optional(&self.db)
+            .await?;
+        let now = chrono::Utc::now().timestamp();
+        let Some(info) = info.filter(|info| info.expires_at.is_none_or(|expires_at| expires_at >= now)) else {
+            return Ok(None);
+        };
+        sqlx::query("UPDATE api_keys SET last_used_at = ? WHERE id = ?")
+            .bind(now)
+            .bind(info.id)
+            .execute(&self.db)
+            .await?;
+
+        let Some(mut user) = self.get_user_by_id(info.user_id).await? else {
+            return Ok(None);
+        };
+        if let Some(scopes) = info.scopes {
+            // The scoped key never act as admin.
+            user.capabilities = Capability::ALL
+                .into_iter()
+                .filter(|capability| scopes.contains(capability) && user.can(*capability))
+                .collect();
+            user.is_admin = false;
+        }
+        Ok(Some(user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::{model::LoginFailure, UserSystem, ACCOUNT_LIMIT, AUTH_LOG_RETENTION_SECONDS};
+
+    #[tokio::test]
+    async fn count_throttled_attempts_in_one_log() {
+        let db = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        let user_system = UserSystem::new(db).await.unwrap();
+        for _ in 0..ACCOUNT_LIMIT.lockout {
+            user_system.login_throttle.failed("user:alice", ACCOUNT_LIMIT).await;
+        }
+        for _ in 0..20 {
+            let result = user_system.login("alice", "wrong", "10.0.0.1").await;
+            assert!(matches!(result, Err(LoginFailure::Throttled(_))));
+        }
+        let logs = user_system.get_auth_logs(0, 100, Some("alice")).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].note.as_deref(), Some("throttled"));
+        assert_eq!(logs[0].attempts, 20);
+    }
+
+    #[tokio::test]
+    async fn prune_expired_logs() {
+        let db = SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        let user_system = UserSystem::new(db.clone()).await.unwrap();
+        let expired = chrono::Utc::now().timestamp() - AUTH_LOG_RETENTION_SECONDS - 1;
+        sqlx::query("INSERT INTO auth_log (username, ip, success, created_at) VALUES ('alice', '10.0.0.1', 0, ?)")
+            .bind(expired)
+            .execute(&db)
+            .await
+            .unwrap();
+        user_system.record_auth("alice", "10.0.0.1", true, None).await.unwrap();
+        let logs = user_system.get_auth_logs(0, 100, None).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].success);
     }
 }
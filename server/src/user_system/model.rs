@@ -1,18 +1,186 @@
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::db::DbError;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UserToCreate {
     pub alias: String,
     pub username: String,
     pub password: String,
 }
 
-#[derive(Debug, Hash, Clone, FromRow, Serialize, Deserialize)]
+#[derive(Debug, Hash, Clone, FromRow, Serialize, Deserialize, ToSchema)]
 pub struct UserInfo {
     pub id: i64,
     pub username: String,
     pub password: String,
     pub alias: String,
     pub is_admin: bool,
-}
\ No newline at end of file
+    /// Switch off the download of the user even if the role can.
+    pub can_download: bool,
+    pub role: String,
+    /// Capabilities of the role, admin have all capabilities regardless of it.
+    #[sqlx(json)]
+    pub capabilities: Vec<Capability>,
+    /// Libraries hidden from the user, medias of them can't be reached in any way.
+    #[sqlx(json)]
+    pub hidden_libraries: Vec<String>,
+}
+
+impl UserInfo {
+    pub fn can(&self, capability: Capability) -> bool {
+        if self.is_admin {
+            return true;
+        }
+        if capability == Capability::Download && !self.can_download {
+            return false;
+        }
+        self.capabilities.contains(&capability)
+    }
+
+    pub fn is_guest(&self) -> bool {
+        self.role == GUEST_ROLE
+    }
+}
+
+pub const ADMIN_ROLE: &str = "admin";
+pub const USER_ROLE: &str = "user";
+pub const GUEST_ROLE: &str = "guest";
+
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Stream,
+    Download,
+    Upload,
+    ManagePlaylists,
+    Share,
+    EditTags,
+    TriggerScan,
+    ManageUsers,
+    ManagePlugins,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 9] = [
+        Capability::Stream,
+        Capability::Download,
+        Capability::Upload,
+        Capability::ManagePlaylists,
+        Capability::Share,
+        Capability::EditTags,
+        Capability::TriggerScan,
+        Capability::ManageUsers,
+        Capability::ManagePlugins,
+    ];
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserPermissionsToUpdate {
+    pub can_download: bool,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct RoleInfo {
+    pub name: String,
+    #[sqlx(json)]
+    pub capabilities: Vec<Capability>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyToCreate {
+    pub name: String,
+    /// Limit the key to these capabilities, the key have all capabilities of owner if it is `None`.
+    pub scopes: Option<Vec<Capability>>,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyInfo {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    #[sqlx(json)]
+    pub scopes: Option<Vec<Capability>>,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ApiKeyCreated {
+    pub info: ApiKeyInfo,
+    /// The key is only visible once.
+    pub key: String,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, ToSchema)]
+pub struct AuthLogInfo {
+    pub id: i64,
+    pub username: String,
+    pub ip: String,
+    pub success: bool,
+    pub note: Option<String>,
+    /// Attempts in this entry, the throttled ones of a lockout window are counted in one entry.
+    pub attempts: i64,
+    pub created_at: i64,
+}
+
+/// User authenticated by the OpenID Connect provider.
+#[derive(Debug, Clone)]
+pub struct OidcIdentity {
+    pub subject: String,
+    pub username: String,
+    pub alias: String,
+    pub role: &'static str,
+}
+
+#[derive(Debug)]
+pub enum LoginFailure {
+    /// Too many failed attempts, must wait these seconds.
+    Throttled(i64),
+    Incorrect,
+    Database(DbError),
+}
+
+impl From<DbError> for LoginFailure {
+    fn from(value: DbError) -> Self {
+        LoginFailure::Database(value)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LibraryAccess {
+    pub library: String,
+    pub allowed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RoleToUpdate {
+    pub role: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignedMedia {
+    File,
+    Cover,
+}
+
+impl SignedMedia {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignedMedia::File => "media_file",
+            SignedMedia::Cover => "media_cover",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MediaSignature {
+    pub uid: i64,
+    pub expires: i64,
+    pub signature: String,
+}
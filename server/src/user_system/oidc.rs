@@ -0,0 +1,328 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::{Mutex, OnceCell};
+
+use super::model::{OidcIdentity, ADMIN_ROLE, GUEST_ROLE, USER_ROLE};
+use crate::config::{Config, OidcConfig};
+
+#[cfg(test)]
+pub(crate) mod mock_idp;
+
+/// How long the user can stay in the provider before callback.
+pub const PENDING_EXPIRES_SECONDS: i64 = 10 * 60;
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    /// Only the id token signed by these algorithms are accepted.
+    #[serde(default = "default_id_token_algorithms")]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+/// The spec requires the providers support `RS256`.
+fn default_id_token_algorithms() -> Vec<String> {
+    vec!["RS256".to_owned()]
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug)]
+struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    created_at: i64,
+}
+
+/// Client of the OpenID Connect authorization code flow.
+#[derive(Debug, Clone)]
+pub struct OidcClient {
+    config: Arc<OidcConfig>,
+    redirect_url: String,
+    http: reqwest::Client,
+    metadata: Arc<OnceCell<ProviderMetadata>>,
+    pending: Arc<Mutex<HashMap<String, PendingLogin>>>,
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl OidcClient {
+    /// Return `None` if the single sign-on is not configured.
+    pub fn new(config: &Config) -> Result<Option<Self>> {
+        let Some(oidc) = config.oidc.clone() else {
+            return Ok(None);
+        };
+        let redirect_url = match &oidc.redirect_url {
+            Some(url) => url.clone(),
+            None if !config.public_url.is_empty() => {
+                format!("{}/api/oidc/callback", config.public_url.trim_end_matches('/'))
+            }
+            None => bail!("The `oidc.redirect_url` or `public_url` must be settled to use the single sign-on."),
+        };
+        Ok(Some(Self {
+            config: Arc::new(oidc),
+            redirect_url,
+            http: reqwest::Client::new(),
+            metadata: Arc::new(OnceCell::new()),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }))
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let issuer = self.config.issuer_url.trim_end_matches('/');
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(format!("{issuer}/.well-known/openid-configuration"))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .with_context(|| "Deserialize provider metadata failed!")?;
+                if metadata.issuer.trim_end_matches('/') != issuer {
+                    bail!("Issuer of provider metadata is mismatched.");
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Begin a login, return the url of provider the user should be redirected to and the state of login.
+    pub async fn authorize_url(&self) -> Result<(String, String)> {
+        let metadata = self.metadata().await?;
+        let state = random_string();
+        let nonce = random_string();
+        let verifier = random_string();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let url = Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.redirect_url),
+                ("scope", &self.config.scopes.join(" ")),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )?;
+
+        let now = chrono::Utc::now().timestamp();
+        let mut pending = self.pending.lock().await;
+        pending.retain(|_, login| now - login.created_at < PENDING_EXPIRES_SECONDS);
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce,
+                verifier,
+                created_at: now,
+            },
+        );
+        Ok((url.to_string(), state))
+    }
+
+    /// Finish the login with the callback of provider, return the identity of user.
+    pub async fn authenticate(&self, code: &str, state: &str) -> Result<OidcIdentity> {
+        let login = self
+            .pending
+            .lock()
+            .await
+            .remove(state)
+            .filter(|login| chrono::Utc::now().timestamp() - login.created_at < PENDING_EXPIRES_SECONDS)
+            .ok_or_else(|| anyhow!("Unknown or expired login state."))?;
+        let metadata = self.metadata().await?;
+        let token: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .basic_auth(&self.config.client_id, Some(&self.config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_url),
+                ("code_verifier", &login.verifier),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| "Deserialize token response failed!")?;
+
+        let claims = self.verify_id_token(metadata, &token.id_token).await?;
+        if claims.get("nonce").and_then(Value::as_str) != Some(login.nonce.as_str()) {
+            bail!("Nonce of id token is mismatched.");
+        }
+        self.to_identity(&claims)
+    }
+
+    async fn verify_id_token(&self, metadata: &ProviderMetadata, id_token: &str) -> Result<HashMap<String, Value>> {
+        let header = jsonwebtoken::decode_header(id_token)?;
+        // The header is not verified yet, so its algorithm must be one of the provider.
+        let supported = metadata
+            .id_token_signing_alg_values_supported
+            .iter()
+            .any(|alg| Algorithm::from_str(alg).is_ok_and(|alg| alg == header.alg));
+        if !supported {
+            bail!("The id token is signed by {:?} which the provider doesn't support.", header.alg);
+        }
+        let key = match header.alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 if self.config.allow_hs_id_token => {
+                DecodingKey::from_secret(self.config.client_secret.as_bytes())
+            }
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                bail!("The id token signed by the client secret is rejected unless `oidc.allow_hs_id_token` is enabled.")
+            }
+            _ => {
+                // Fetch every time so the rotated keys are always picked up, login is rare enough.
+                let jwks: JwkSet = self.http.get(&metadata.jwks_uri).send().await?.error_for_status()?.json().await?;
+                let jwk = match &header.kid {
+                    Some(kid) => jwks.find(kid),
+                    None => jwks.keys.first(),
+                }
+                .ok_or_else(|| anyhow!("No found the key of id token."))?;
+                DecodingKey::from_jwk(jwk)?
+            }
+        };
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+        Ok(jsonwebtoken::decode(id_token, &key, &validation)?.claims)
+    }
+
+    fn to_identity(&self, claims: &HashMap<String, Value>) -> Result<OidcIdentity> {
+        let claim = |name: &str| claims.get(name).and_then(Value::as_str).map(str::to_owned);
+        let subject = claim("sub").ok_or_else(|| anyhow!("Missing the `sub` claim."))?;
+        let username = claim(&self.config.username_claim)
+            .filter(|username| !username.is_empty())
+            .ok_or_else(|| anyhow!("Missing the `{}` claim.", self.config.username_claim))?;
+        let groups: Vec<&str> = match claims.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(group)) => vec![group.as_str()],
+            _ => vec![],
+        };
+        let in_group = |group: &Option<String>| group.as_ref().is_some_and(|group| groups.contains(&group.as_str()));
+
+        if self.config.allowed_group.is_some() && !in_group(&self.config.allowed_group) {
+            bail!("User `{username}` is not in the allowed group.");
+        }
+        let role = if in_group(&self.config.admin_group) {
+            ADMIN_ROLE
+        } else if in_group(&self.config.guest_group) {
+            GUEST_ROLE
+        } else {
+            USER_ROLE
+        };
+        Ok(OidcIdentity {
+            subject,
+            alias: claim("name").unwrap_or_else(|| username.clone()),
+            username,
+            role,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::Algorithm;
+    use serde_json::json;
+
+    use super::{mock_idp::Provider, OidcClient};
+    use crate::user_system::{model::ADMIN_ROLE, oidc::mock_idp::CODE};
+
+    /// Begin a login and let the provider authorize it like the user approved, return the state.
+    async fn authorize(provider: &Provider, client: &OidcClient) -> String {
+        let (url, state) = client.authorize_url().await.unwrap();
+        assert_eq!(provider.approve(&url).await, state);
+        state
+    }
+
+    #[actix_web::test]
+    async fn login_with_code_and_pkce() {
+        let provider = Provider::start().await;
+        let client = provider.client(false).await;
+        let state = authorize(&provider, &client).await;
+        // The id token is signed by `RS256` and verified by the key in JWKS.
+        let identity = client.authenticate(CODE, &state).await.unwrap();
+        assert_eq!(identity.subject, "subject-1");
+        assert_eq!(identity.username, "alice");
+        assert_eq!(identity.alias, "Alice");
+        assert_eq!(identity.role, ADMIN_ROLE);
+        // The state can only be used once.
+        assert!(client.authenticate(CODE, &state).await.is_err());
+        provider.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn reject_unknown_state_and_wrong_verifier() {
+        let provider = Provider::start().await;
+        let client = provider.client(false).await;
+        authorize(&provider, &client).await;
+        assert!(client.authenticate(CODE, "forged-state").await.is_err());
+
+        let state = authorize(&provider, &client).await;
+        provider.idp.lock().await.challenge = "challenge-of-another-login".to_owned();
+        assert!(client.authenticate(CODE, &state).await.is_err());
+        provider.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn reject_invalid_id_token() {
+        let provider = Provider::start().await;
+        let client = provider.client(false).await;
+        let now = chrono::Utc::now().timestamp();
+        let invalid_claims = [
+            json!({ "nonce": "replayed-nonce" }),
+            json!({ "aud": "another-client" }),
+            json!({ "iss": "http://another.issuer" }),
+            json!({ "exp": now - 3600 }),
+            json!({ "preferred_username": "" }),
+        ];
+        for claims in invalid_claims {
+            let state = authorize(&provider, &client).await;
+            provider.idp.lock().await.claims = claims.clone();
+            assert!(client.authenticate(CODE, &state).await.is_err(), "accepted the id token with {claims}");
+        }
+        provider.stop().await;
+    }
+
+    #[actix_web::test]
+    async fn pin_algorithm_of_id_token() {
+        let provider = Provider::start().await;
+        // Signed by the client secret but the provider only publishes `RS256`.
+        provider.idp.lock().await.alg = Algorithm::HS256;
+        let client = provider.client(true).await;
+        let state = authorize(&provider, &client).await;
+        assert!(client.authenticate(CODE, &state).await.is_err());
+
+        // Published by the provider, but not enabled by config.
+        provider.idp.lock().await.algorithms = vec!["RS256", "HS256"];
+        let client = provider.client(false).await;
+        let state = authorize(&provider, &client).await;
+        assert!(client.authenticate(CODE, &state).await.is_err());
+
+        let client = provider.client(true).await;
+        let state = authorize(&provider, &client).await;
+        assert_eq!(client.authenticate(CODE, &state).await.unwrap().username, "alice");
+        provider.stop().await;
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use tokio::sync::Mutex;
+
+/// Limit of failed login attempts for one key.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    /// Failures allowed before backoff begin.
+    pub free: u32,
+    /// Failures cause the key locked out.
+    pub lockout: u32,
+}
+
+pub const ACCOUNT_LIMIT: Limit = Limit { free: 3, lockout: 10 };
+pub const IP_LIMIT: Limit = Limit { free: 10, lockout: 30 };
+
+const MAX_BACKOFF_SECONDS: i64 = 60;
+pub const LOCKOUT_SECONDS: i64 = 15 * 60;
+/// Failures older than this are forgotten.
+const FORGET_SECONDS: i64 = 60 * 60;
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Attempt {
+    failures: u32,
+    last_failed_at: i64,
+    blocked_until: i64,
+}
+
+/// Throttle failed login attempts with exponential backoff and temporary lockout.
+#[derive(Debug, Default)]
+pub struct LoginThrottle {
+    attempts: Mutex<HashMap<String, Attempt>>,
+}
+
+impl LoginThrottle {
+    /// Return the seconds must wait before next attempt of any keys.
+    pub async fn retry_after(&self, keys: &[&str]) -> Option<i64> {
+        let now = chrono::Utc::now().timestamp();
+        let attempts = self.attempts.lock().await;
+        keys.iter()
+            .filter_map(|key| attempts.get(*key))
+            .map(|attempt| attempt.blocked_until - now)
+            .filter(|wait| *wait > 0)
+            .max()
+    }
+
+    pub async fn failed(&self, key: &str, limit: Limit) {
+        let now = chrono::Utc::now().timestamp();
+        let mut attempts = self.attempts.lock().await;
+        if attempts.len() > PRUNE_THRESHOLD {
+            attempts.retain(|_, attempt| now - attempt.last_failed_at < FORGET_SECONDS || attempt.blocked_until > now);
+        }
+        let attempt = attempts.entry(key.to_owned()).or_insert(Attempt {
+            failures: 0,
+            last_failed_at: now,
+            blocked_until: 0,
+        });
+        if now - attempt.last_failed_at >= FORGET_SECONDS {
+            attempt.failures = 0;
+        }
+        attempt.failures += 1;
+        attempt.last_failed_at = now;
+        if attempt.failures >= limit.lockout {
+            attempt.blocked_until = now + LOCKOUT_SECONDS;
+        } else if attempt.failures > limit.free {
+            let exp = attempt.failures - limit.free - 1;
+            attempt.blocked_until = now + 2i64.saturating_pow(exp).min(MAX_BACKOFF_SECONDS);
+        }
+    }
+
+    pub async fn succeeded(&self, key: &str) {
+        self.attempts.lock().await.remove(key);
+    }
+}
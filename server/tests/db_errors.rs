@@ -0,0 +1,97 @@
+use diosic::{
+    config::SharedConfig,
+    db::DbError,
+    library_system::LibrarySystem,
+    share_system::ShareSystem,
+    user_system::{model::USER_ROLE, model::UserToCreate, UserSystem},
+};
+use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+
+/// The memory database only lives in one connection.
+async fn memory_db() -> Pool<Sqlite> {
+    SqlitePoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap()
+}
+
+fn config() -> SharedConfig {
+    let config = serde_json::json!({
+        "libraries": [],
+        "data_path": null,
+        "covers_cached_path": null,
+        "host": "127.0.0.1",
+        "port": 3177,
+    });
+    SharedConfig::new(serde_json::from_value(config).unwrap())
+}
+
+async fn insert_media(db: &Pool<Sqlite>, id: i64, cover_path: Option<&str>) {
+    sqlx::query(
+        "INSERT INTO medias(id, path, title, album, artist, genre, year, library, cover_path, duration_seconds, file_name, file_type)
+        VALUES (?, '/music/song.flac', 'Song', 'Album', 'Artist', 'Genre', 2024, 'Music', ?, 180, 'song.flac', 'flac')",
+    )
+    .bind(id)
+    .bind(cover_path)
+    .execute(db)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn missing_user_is_not_found() {
+    let user_system = UserSystem::new(memory_db().await).await.unwrap();
+    assert!(matches!(user_system.get_user("nobody").await, Err(DbError::NotFound(_))));
+    assert!(user_system.get_user_by_id(42).await.unwrap().is_none());
+    assert!(!user_system.exists_user("nobody").await.unwrap());
+}
+
+#[tokio::test]
+async fn missing_media_is_none() {
+    let library_system = LibrarySystem::new(memory_db().await, config()).await.unwrap();
+    assert!(library_system.get_media_info_by_id(1, &[]).await.unwrap().is_none());
+    assert!(library_system.get_media_file_by_id(1, &[]).await.unwrap().is_none());
+    assert!(library_system.get_media_cover_file_by_id(1, &[]).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn hidden_media_is_none() {
+    let db = memory_db().await;
+    let library_system = LibrarySystem::new(db.clone(), config()).await.unwrap();
+    insert_media(&db, 1, Some("/music/song.jpg")).await;
+    assert!(library_system.get_media_file_by_id(1, &[]).await.unwrap().is_some());
+    assert!(library_system.get_media_file_by_id(1, &["Music".to_owned()]).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn null_cover_path_is_none() {
+    let db = memory_db().await;
+    let library_system = LibrarySystem::new(db.clone(), config()).await.unwrap();
+    insert_media(&db, 1, None).await;
+    assert!(library_system.get_media_cover_file_by_id(1, &[]).await.unwrap().is_none());
+    let media = library_system.get_media_info_by_id(1, &[]).await.unwrap().unwrap();
+    assert!(media.cover_path.is_none());
+}
+
+#[tokio::test]
+async fn undecodable_column_is_error() {
+    let db = memory_db().await;
+    let user_system = UserSystem::new(db.clone()).await.unwrap();
+    let user = UserToCreate {
+        alias: "Alice".to_owned(),
+        username: "alice".to_owned(),
+        password: "password1".to_owned(),
+    };
+    user_system.create_user(user, USER_ROLE).await.unwrap();
+    sqlx::query("UPDATE roles SET capabilities = 'not json' WHERE name = ?")
+        .bind(USER_ROLE)
+        .execute(&db)
+        .await
+        .unwrap();
+    assert!(matches!(user_system.get_user("alice").await, Err(DbError::Sqlx(_))));
+    assert!(matches!(user_system.get_roles().await, Err(DbError::Sqlx(_))));
+}
+
+#[tokio::test]
+async fn missing_share_is_none() {
+    let share_system = ShareSystem::new(memory_db().await).await.unwrap();
+    assert!(share_system.get_share("unknown").await.unwrap().is_none());
+    assert_eq!(share_system.get_total_share(None).await.unwrap(), 0);
+}
@@ -1,13 +1,18 @@
 type useFetchType = typeof useFetch;
 
+/** Url of the API path, it follows the same base as the fetches. */
+export const apiUrl = (path: string) => {
+    const config = useRuntimeConfig();
+    return `${import.meta.dev ? config.public.devBaseUrl : config.public.baseUrl}/api${path}`;
+};
+
 export const useApiFetch: useFetchType = (path, opts = {}) => {
     const auth = useCookie(AUTH_COOKIE_NAME);
-    const config = useRuntimeConfig();
     const headers: any = {}
     if (auth.value) {
         headers["X-Authorization"] = auth.value.toString();
     }
-    opts.baseURL = `${import.meta.dev ? config.public.devBaseUrl : config.public.baseUrl}/api`;
+    opts.baseURL = apiUrl('');
     opts.headers = headers;
     opts.key = `${path}${JSON.stringify(opts.query)}`;
     // opts.credentials = 'include'; // cloudflare workers will broken.
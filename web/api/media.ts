@@ -1,11 +1,10 @@
 import { useApiFetch } from "./customFetch";
-import type { PubMediaInfo, GetMediasQuery, ListSlice } from "./model";
+import type { PubMediaInfo, GetMediasQuery, ListSlice, SignedMediaUrl } from "./model";
 
-export function getMediaFileAddress(id: number): string {
-    const auth = useCookie(AUTH_COOKIE_NAME).value;
-    const config = useRuntimeConfig();  
-    const origin = import.meta.dev? config.public.devBaseUrl : config.public.baseUrl;
-    return `${origin}/api/media_file/${id}?auth=${auth}`;
+export async function getMediaUrl(id: number) {
+    return useApiFetch<SignedMediaUrl>(`/media_url/${id}`, {
+        watch: false
+    })
 }
 
 export async function getMedias(query: GetMediasQuery) {
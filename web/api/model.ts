@@ -20,6 +20,41 @@ export interface UserInfo {
     password: string;
     alias: string;
     is_admin: boolean;
+    can_download: boolean;
+    role: string;
+    capabilities: Capability[];
+    hidden_libraries: string[];
+}
+
+export interface UserPermissionsToUpdate {
+    can_download: boolean;
+}
+
+export interface LibraryAccess {
+    library: string;
+    allowed: boolean;
+}
+
+export interface ApiKeyInfo {
+    id: number;
+    user_id: number;
+    name: string;
+    scopes?: Capability[];
+    expires_at?: number;
+    created_at: number;
+    last_used_at?: number;
+}
+
+export interface ApiKeyCreated {
+    info: ApiKeyInfo;
+    key: string;
+}
+
+export type Capability = "stream" | "download" | "upload" | "manage_playlists" | "share" | "edit_tags" | "trigger_scan" | "manage_users" | "manage_plugins";
+
+export interface RoleInfo {
+    name: string;
+    capabilities: Capability[];
 }
 
 export interface ServerInfo {
@@ -29,6 +64,7 @@ export interface ServerInfo {
     admin_required: boolean;
     guest_enable: boolean;
     guest_password_required: boolean;
+    oidc_enable: boolean;
 }
 
 export interface SourceInfo {
@@ -56,6 +92,12 @@ export interface PubMediaInfo {
     file_type: string;
 }
 
+export interface SignedMediaUrl {
+    file_url: string;
+    cover_url?: string;
+    expires: number;
+}
+
 export interface ListSlice<T> {
     items: T[];
     total: number;
@@ -90,7 +132,7 @@ export interface ToSetup {
     guest_password?: string;
 }
 
-export interface LoginQuery {
+export interface LoginBody {
     username: string;
     password: string;
 }
@@ -100,21 +142,22 @@ export interface LoginedResult {
     token: string;
 }
 
-export interface LogoutQuery {
-    token: string;
-}
-
-export interface SearchUserQuery {
-    content: string;
-    index: number;
-    limit: number;
+export interface ApiErrorBody {
+    code: string;
+    message: string;
+    details?: any;
 }
 
-export interface AuthQuery {
-    auth: string;
+export interface AuthLogInfo {
+    id: number;
+    username: string;
+    ip: string;
+    success: boolean;
+    note?: string;
+    attempts: number;
+    created_at: number;
 }
 
-export interface GetPluginQuery {
-    index: number;
-    limit: number;
+export interface LogoutQuery {
+    token: string;
 }
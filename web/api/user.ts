@@ -1,5 +1,5 @@
 import { useApiFetch } from "./customFetch";
-import type { GetUsersQuery, ListSlice, LoginQuery, LoginedResult, LogoutQuery, ToSetup, UserInfo, UserToCreate } from "./model";
+import type { GetUsersQuery, ListSlice, LoginBody, LoginedResult, LogoutQuery, ToSetup, UserInfo, UserToCreate } from "./model";
 
 export async function getUser(username: string) {
     return useApiFetch<UserInfo>(`/users/${username}`)
@@ -34,10 +34,11 @@ export async function createUser(body: UserToCreate) {
     })
 }
 
-export async function login(query: LoginQuery) {
+export async function login(body: LoginBody) {
     return useApiFetch<LoginedResult>(`/login`, {
         watch: false,
-        query
+        method: 'POST',
+        body
     })
 }
 
@@ -1,6 +1,6 @@
 import type { GetMediasQuery, PubMediaInfo } from "~/api/model"
 import { Howl, Howler } from 'howler';
-import { getMedia, getMediaFileAddress, getMedias } from "~/api/media";
+import { getMedia, getMediaUrl, getMedias } from "~/api/media";
 
 export enum PlayMode {
     Order,
@@ -70,13 +70,17 @@ function setElapsedTime(clearOnly: boolean = false) {
     }
 }
 
-function loadHowl() {
+async function loadHowl() {
     const state = useMediaPlayerState().value;
     if (state.current) {
         unloadHowl();
-        console.log(document.title)
+        const { data, error } = await getMediaUrl(state.current.id);
+        if (error.value || !data.value) {
+            console.error(error.value);
+            return;
+        }
         const sound = new Howl({
-            src: getMediaFileAddress(state.current.id),
+            src: data.value.file_url,
             format: state.current.file_type,
             onplay() {
                 state.playing = true;
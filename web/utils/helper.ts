@@ -1,4 +1,4 @@
-import { Source, type LoginQuery } from "~/api/model";
+import { Source, type LoginBody } from "~/api/model";
 import { login, logout } from "~/api/user";
 export const AUTH_COOKIE_NAME = "authorization";
 
@@ -26,20 +26,12 @@ export function toHHMMSS(sec_num: number) {
         .join(":");
 }
 
-export function setAuthQuery(url?: string) {
-    const auth = useCookie(AUTH_COOKIE_NAME).value;
-    if (url && auth) {
-        return url.search(/\?{1}/) == -1 ? `${url}?auth=${auth}` : `${url}&auth=${auth}`
-    }
-    return url;
-}
-
 
-export async function loginNow(query: LoginQuery) {
+export async function loginNow(query: LoginBody) {
     const { data, error } = await login(query);
     if (error.value) {
             const toast = useToast();
-            if (error.value.statusCode === 404) {
+            if (error.value.statusCode === 401) {
             toast.add({
                 color: 'red',
                 title: 'Username or password is wrong!'
@@ -47,7 +39,7 @@ export async function loginNow(query: LoginQuery) {
         } else {
             toast.add({
                 color: 'red',
-                title: error.value.data
+                title: error.value.data?.message
             })
         }
         console.error(error.value);